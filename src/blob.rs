@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct Blob {
+    pub id: String,
+    pub name: String,
+    pub mime: String,
+    pub size: usize,
+    // 所属频道，私有频道的附件需要验证订阅者
+    #[serde(default)]
+    pub channel: String,
+}
+
+const STORE: &str = "blob";
+// 附件文件存放在db_path下的目录
+const BLOB_DIR: &str = "blob";
+
+lazy_static! {
+    pub static ref INTERFACE: BlobInterface = BlobInterface::new();
+}
+
+pub struct BlobInterface {
+    // 附件信息 id/blob
+    storage: super::storage::SingleKvStorage,
+    path: PathBuf,
+}
+
+// 根据文件头判断类型，不信任上传时声明的类型
+pub fn sniff_mime(data: &[u8]) -> String {
+    match infer::Infer::new().get(data) {
+        Some(info) => info.mime,
+        None => match std::str::from_utf8(data) {
+            Ok(_) => "text/plain; charset=utf-8".to_string(),
+            Err(_) => "application/octet-stream".to_string(),
+        },
+    }
+}
+
+impl BlobInterface {
+    pub fn new() -> BlobInterface {
+        let path = std::path::Path::new(&super::CONFIG.db_path).join(BLOB_DIR);
+        fs::create_dir_all(&path).unwrap();
+        BlobInterface {
+            storage: super::storage::SingleKvStorage::new(&super::CONFIG.db_path, STORE),
+            path,
+        }
+    }
+
+    // 返回附件id
+    pub fn add_blob(&self, channel: &str, name: &str, data: &[u8]) -> String {
+        let id = uuid::Uuid::new_v4().to_simple().to_string();
+        // 文件名会拼进详情页的markdown，去掉会破坏格式的字符
+        let name: String = name
+            .chars()
+            .map(|c| match c {
+                '`' | '$' | '\\' | '[' | ']' | '(' | ')' | '<' | '>' | '"' => '_',
+                _ => c,
+            })
+            .collect();
+        let blob = Blob {
+            id: id.clone(),
+            name,
+            mime: sniff_mime(data),
+            size: data.len(),
+            channel: channel.to_string(),
+        };
+        debug!("new blob:{:?}", blob);
+        fs::write(self.path.join(&id), data).unwrap();
+        let json_string = serde_json::to_string(&blob).unwrap();
+        self.storage
+            .put_single(&id, &rkv::Value::Json(&json_string));
+        id
+    }
+
+    pub fn get_blob(&self, id: &str) -> Result<Blob, &str> {
+        match self.storage.get_single(id) {
+            Some(blob_string) => Ok(serde_json::from_str(&blob_string).unwrap()),
+            None => Err("没找到对应附件"),
+        }
+    }
+
+    pub fn read_blob(&self, id: &str) -> Result<(Blob, Vec<u8>), &str> {
+        let blob = self.get_blob(id)?;
+        match fs::read(self.path.join(&blob.id)) {
+            Ok(data) => Ok((blob, data)),
+            Err(_) => Err("没找到对应附件"),
+        }
+    }
+
+    pub fn del_blob(&self, id: &str) {
+        debug!("del blob:{}", id);
+        let _ = fs::remove_file(self.path.join(id));
+        self.storage.del_single(id);
+    }
+}
@@ -2,6 +2,142 @@ use rkv;
 use serde_json;
 use uuid;
 
+// 默认sendkey的名称
+pub const DEFAULT_KEY_LABEL: &str = "default";
+// sendkey可以授予的权限
+pub const SCOPES: [&str; 3] = ["send", "edit", "recall"];
+// 旧sendkey最多保留30天
+const GRACE_MAX_MINUTES: i64 = 30 * 24 * 60;
+
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct SendKey {
+    pub key: String,
+    pub label: String,
+    pub created: i64,
+    // 过期时间，0表示不过期
+    #[serde(default)]
+    pub expires: i64,
+    #[serde(default)]
+    pub last_used: i64,
+    // 允许的操作，为空表示全部允许
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+impl SendKey {
+    fn new(label: &str, expires: i64, scopes: Vec<String>) -> SendKey {
+        SendKey {
+            key: uuid::Uuid::new_v4().to_simple().to_string(),
+            label: label.to_string(),
+            created: chrono::Local::now().timestamp(),
+            expires,
+            last_used: 0,
+            scopes,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires != 0 && self.expires <= chrono::Local::now().timestamp()
+    }
+
+    pub fn allows(&self, scope: &str) -> bool {
+        self.scopes.is_empty() || self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+// 频道的订阅方式
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    // 任何人都可以直接订阅
+    Open,
+    // 订阅需要频道创建者审核
+    Approval,
+    // 只能通过邀请订阅
+    Invite,
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Visibility::Open
+    }
+}
+
+impl Visibility {
+    pub fn parse(visibility: &str) -> Option<Visibility> {
+        match visibility {
+            "open" => Some(Visibility::Open),
+            "approval" => Some(Visibility::Approval),
+            "invite" => Some(Visibility::Invite),
+            _ => None,
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Visibility::Open => "公开",
+            Visibility::Approval => "需要审核",
+            Visibility::Invite => "仅限邀请",
+        }
+    }
+}
+
+// 用户在频道中的角色，从低到高排列
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Role {
+    // 订阅者
+    Member,
+    // 可以管理频道，但不能删除频道和任免管理员
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Role::Member => "订阅者",
+            Role::Admin => "管理员",
+            Role::Owner => "创建者",
+        }
+    }
+}
+
+// 等待审核的订阅申请
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct SubscribeRequest {
+    pub id: String,
+    pub channel: String,
+    pub user: String,
+    pub time: i64,
+    // 申请时指定的标签，通过后生效
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+// 邀请码，用于邀请用户订阅频道
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct Invite {
+    pub code: String,
+    pub channel: String,
+    // 最多使用次数，0表示不限
+    pub max_uses: u32,
+    pub uses: u32,
+    // 过期时间，0表示不过期
+    pub expires: i64,
+    pub created: i64,
+}
+
+impl Invite {
+    pub fn is_valid(&self) -> bool {
+        (self.max_uses == 0 || self.uses < self.max_uses)
+            && (self.expires == 0 || self.expires > chrono::Local::now().timestamp())
+    }
+}
+
+// 二维码场景值的前缀
+pub const INVITE_SCENE_PREFIX: &str = "invite_";
+pub const SUBSCRIBE_SCENE_PREFIX: &str = "subscribe_";
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct Channel {
     pub id: String,
@@ -9,9 +145,114 @@ pub struct Channel {
     pub name: String,
     pub owner: String,
     pub subscribers: Vec<String>,
+    // 私密频道的内容页只对订阅者展示
+    #[serde(default)]
+    pub private: bool,
+    // 订阅源的访问凭证，与sendkey分开
+    #[serde(default)]
+    pub feed_token: String,
+    // 所有可用的sendkey，包括默认的sendkey
+    #[serde(default)]
+    pub keys: Vec<SendKey>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    // 禁止订阅的用户
+    #[serde(default)]
+    pub banned: Vec<String>,
+    // 管理员
+    #[serde(default)]
+    pub admins: Vec<String>,
+    // 等待确认的新创建者
+    #[serde(default)]
+    pub pending_owner: Option<String>,
+    #[serde(default)]
+    pub description: String,
+    // 便于输入的短名称，可以代替频道id使用
+    #[serde(default)]
+    pub handle: String,
+    // 是否在频道目录中展示
+    #[serde(default)]
+    pub listed: bool,
+}
+
+impl Channel {
+    pub fn role_of(&self, user: &str) -> Option<Role> {
+        if self.owner == user {
+            Some(Role::Owner)
+        } else if self.admins.iter().any(|usr| usr == user) {
+            Some(Role::Admin)
+        } else if self.subscribers.iter().any(|usr| usr == user) {
+            Some(Role::Member)
+        } else {
+            None
+        }
+    }
+
+    // 创建者或者管理员
+    pub fn is_manager(&self, user: &str) -> bool {
+        matches!(self.role_of(user), Some(role) if role >= Role::Admin)
+    }
+
+    // 旧数据没有keys，补上默认sendkey
+    fn ensure_default_key(&mut self) {
+        if !self.keys.iter().any(|key| key.key == self.sendkey) {
+            self.keys.insert(
+                0,
+                SendKey {
+                    key: self.sendkey.clone(),
+                    label: DEFAULT_KEY_LABEL.to_string(),
+                    created: 0,
+                    expires: 0,
+                    last_used: 0,
+                    scopes: Vec::new(),
+                },
+            );
+        }
+    }
 }
 
 const STORE: &str = "channel";
+const STORE_SENDKEY: &str = "channel_sendkey";
+const STORE_REQUEST: &str = "channel_request";
+const STORE_INVITE: &str = "channel_invite";
+const STORE_HANDLE: &str = "channel_handle";
+const STORE_QRCODE: &str = "channel_qrcode";
+
+// 是否超过数量限制，白名单用户和0表示不限制
+pub fn exceeds_quota(user: &str, count: usize, limit: usize) -> bool {
+    limit > 0 && count >= limit && !super::CONFIG.quota_whitelist.iter().any(|usr| usr == user)
+}
+
+// 频道订阅二维码和邀请码二维码
+#[derive(Debug, Deserialize, Clone, Serialize)]
+struct Qrcode {
+    url: String,
+    expires: i64,
+    // 下次生成的时间
+    refresh_at: i64,
+}
+
+// 过期前一天重新生成二维码，生成失败时一小时后重试
+const QRCODE_REFRESH_BEFORE: i64 = 24 * 3600;
+const QRCODE_RETRY: i64 = 3600;
+
+// handle的最大长度，比频道id短，避免和频道id混淆
+const HANDLE_MAX_LEN: usize = 24;
+
+// handle只能包含小写字母、数字和-，以字母开头
+fn check_handle(handle: &str) -> Result<(), &'static str> {
+    if handle.len() < 2 || handle.len() > HANDLE_MAX_LEN {
+        return Err("handle长度需要在2到24之间");
+    }
+    if !handle.starts_with(|c: char| c.is_ascii_lowercase())
+        || !handle
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err("handle只能包含小写字母、数字和-，并以字母开头");
+    }
+    Ok(())
+}
 
 lazy_static! {
     pub static ref INTERFACE: ChannelInterface = ChannelInterface::new();
@@ -19,30 +260,80 @@ lazy_static! {
 
 pub struct ChannelInterface {
     storage: super::storage::SingleKvStorage,
+    // sendkey/频道id 索引
+    storage_sendkey: super::storage::SingleKvStorage,
+    // id/订阅申请
+    storage_request: super::storage::SingleKvStorage,
+    // 邀请码/邀请
+    storage_invite: super::storage::SingleKvStorage,
+    // handle/频道id 索引
+    storage_handle: super::storage::SingleKvStorage,
+    // 频道id/订阅二维码，和频道分开保存，刷新时不会覆盖频道数据
+    storage_qrcode: super::storage::SingleKvStorage,
 }
 
 impl ChannelInterface {
     pub fn new() -> ChannelInterface {
         ChannelInterface {
             storage: super::storage::SingleKvStorage::new(&super::CONFIG.db_path, STORE),
+            storage_sendkey: super::storage::SingleKvStorage::new(
+                &super::CONFIG.db_path,
+                STORE_SENDKEY,
+            ),
+            storage_request: super::storage::SingleKvStorage::new(
+                &super::CONFIG.db_path,
+                STORE_REQUEST,
+            ),
+            storage_invite: super::storage::SingleKvStorage::new(
+                &super::CONFIG.db_path,
+                STORE_INVITE,
+            ),
+            storage_handle: super::storage::SingleKvStorage::new(
+                &super::CONFIG.db_path,
+                STORE_HANDLE,
+            ),
+            storage_qrcode: super::storage::SingleKvStorage::new(
+                &super::CONFIG.db_path,
+                STORE_QRCODE,
+            ),
         }
     }
 
     // 返回id
     pub fn add_channel(&self, name: &str, owner: &str) -> Result<String, &str> {
+        if exceeds_quota(
+            owner,
+            super::user::INTERFACE.get_user(owner)?.owns.len(),
+            super::CONFIG.max_channels_per_owner,
+        ) {
+            return Err("创建的频道数量已达上限");
+        }
         let id = uuid::Uuid::new_v4().to_simple().to_string();
-        let sendkey = uuid::Uuid::new_v4().to_simple().to_string();
+        let default_key = SendKey::new(DEFAULT_KEY_LABEL, 0, Vec::new());
+        let sendkey = default_key.key.clone();
         let channel = Channel {
             id: id.clone(),
             sendkey: sendkey.clone(),
             name: name.to_string(),
             owner: owner.to_string(),
             subscribers: Vec::<String>::new(),
+            private: false,
+            feed_token: String::new(),
+            keys: vec![default_key],
+            visibility: Visibility::Open,
+            banned: Vec::new(),
+            admins: Vec::new(),
+            pending_owner: None,
+            description: String::new(),
+            handle: String::new(),
+            listed: false,
         };
 
         let json_string = serde_json::to_string(&channel).unwrap();
         self.storage
             .put_single(&id, &rkv::Value::Json(&json_string));
+        self.storage_sendkey
+            .put_single(&sendkey, &rkv::Value::Json(&id));
         match super::user::INTERFACE.user_new_channel(owner, &id) {
             Ok(_) => Ok(id),
             Err(err) => Err(err),
@@ -50,7 +341,7 @@ impl ChannelInterface {
     }
 
     pub fn delete_channel(&self, id: &str, owner: &str) -> Result<bool, &str> {
-        match self.get_channel_by_id(id) {
+        match self.check_role(id, owner, Role::Owner) {
             Ok(chn) => {
                 // 先取消所有用户订阅
                 for user in chn.subscribers {
@@ -58,7 +349,27 @@ impl ChannelInterface {
                 }
                 match super::user::INTERFACE.user_del_channel(owner, id) {
                     Ok(_) => {
-                        self.storage.del_single(id);
+                        super::schedule::INTERFACE.clean_channel(id);
+                        for request in self.get_requests_by_channel(id) {
+                            self.storage_request.del_single(&request.id);
+                        }
+                        for invite in self.get_invites_by_channel(id) {
+                            self.storage_invite.del_single(&invite.code);
+                        }
+                        self.storage_sendkey.del_single(&chn.sendkey);
+                        for key in chn.keys {
+                            self.storage_sendkey.del_single(&key.key);
+                        }
+                        // handle和频道在同一个事务中删除
+                        let env = self.storage.env.read().unwrap();
+                        let mut writer = env.write().unwrap();
+                        if let Some(channel_string) = self.storage.get_json(&writer, id) {
+                            let latest: Channel = serde_json::from_str(&channel_string).unwrap();
+                            self.release_handle(&mut writer, &latest);
+                            self.storage.del_json(&mut writer, id);
+                            self.storage_qrcode.del_json(&mut writer, id);
+                        }
+                        writer.commit().unwrap();
                         Ok(true)
                     }
                     err => err,
@@ -67,8 +378,54 @@ impl ChannelInterface {
             Err(err) => Err(err),
         }
     }
-    pub fn subscribe(&self, channel: &str, user: &str) -> Result<bool, &str> {
+    // 返回false表示已经提交订阅申请，等待审核，tags在审核通过后生效
+    pub fn subscribe(&self, channel: &str, user: &str, tags: &[String]) -> Result<bool, &str> {
+        let chn = self.get_channel_by_id(channel)?;
+        if !chn.is_manager(user) {
+            match chn.visibility {
+                Visibility::Open => (),
+                Visibility::Approval => {
+                    if chn.subscribers.iter().any(|usr| usr == user) {
+                        return Err("已经订阅过了");
+                    }
+                    self.add_request(&chn, user, tags)?;
+                    return Ok(false);
+                }
+                Visibility::Invite => return Err("该频道只能通过邀请订阅"),
+            }
+        }
+        self.add_subscriber(channel, user)
+    }
+
+    // 不检查订阅方式直接添加订阅者
+    fn add_subscriber(&self, channel: &str, user: &str) -> Result<bool, &str> {
         match self.get_channel_by_id(channel) {
+            Ok(ref chn) if chn.banned.iter().any(|usr| usr == user) => Err("已被禁止订阅该频道"),
+            Ok(ref chn)
+                if !chn.subscribers.iter().any(|usr| usr == user)
+                    && exceeds_quota(
+                        &chn.owner,
+                        chn.subscribers.len(),
+                        super::CONFIG.max_subscribers_per_channel,
+                    ) =>
+            {
+                Err("频道订阅人数已达上限")
+            }
+            Ok(_)
+                if super::user::INTERFACE
+                    .get_user(user)
+                    .map(|usr| {
+                        !usr.subscribes.iter().any(|chn| chn == channel)
+                            && exceeds_quota(
+                                user,
+                                usr.subscribes.len(),
+                                super::CONFIG.max_subscriptions_per_user,
+                            )
+                    })
+                    .unwrap_or(false) =>
+            {
+                Err("订阅的频道数量已达上限")
+            }
             Ok(mut chn) => match super::user::INTERFACE.user_subscribe(user, channel) {
                 Ok(_) => {
                     chn.subscribers.push(user.to_string());
@@ -102,6 +459,729 @@ impl ChannelInterface {
             Err(err) => Err(err),
         }
     }
+    // 创建者移除订阅者
+    pub fn kick(&self, id: &str, owner: &str, user: &str) -> Result<bool, &str> {
+        let chn = self.check_role(id, owner, Role::Admin)?;
+        if chn.is_manager(user) {
+            return Err("不能移除频道的管理员");
+        }
+        if !chn.subscribers.iter().any(|usr| usr == user) {
+            return Err("该用户没有订阅频道");
+        }
+        self.unsubscribe(id, user)
+    }
+
+    // 移除订阅者并禁止再次订阅
+    pub fn ban(&self, id: &str, owner: &str, user: &str) -> Result<bool, &str> {
+        let mut chn = self.check_role(id, owner, Role::Admin)?;
+        if chn.is_manager(user) {
+            return Err("不能禁止频道的管理员");
+        }
+        if chn.banned.iter().any(|usr| usr == user) {
+            return Err("该用户已被禁止订阅");
+        }
+        chn.banned.push(user.to_string());
+        self.save_channel(&chn);
+        for request in self.get_requests_by_channel(id) {
+            if request.user == user {
+                self.storage_request.del_single(&request.id);
+            }
+        }
+        if chn.subscribers.iter().any(|usr| usr == user) {
+            self.unsubscribe(id, user)?;
+        }
+        Ok(true)
+    }
+
+    pub fn unban(&self, id: &str, owner: &str, user: &str) -> Result<bool, &str> {
+        let mut chn = self.check_role(id, owner, Role::Admin)?;
+        match chn.banned.iter().position(|usr| usr == user) {
+            Some(i) => {
+                chn.banned.remove(i);
+                self.save_channel(&chn);
+                Ok(true)
+            }
+            None => Err("该用户没有被禁止订阅"),
+        }
+    }
+
+    pub fn set_private(&self, id: &str, owner: &str, private: bool) -> Result<bool, &str> {
+        match self.check_role(id, owner, Role::Admin) {
+            Ok(mut chn) => {
+                chn.private = private;
+                self.save_channel(&chn);
+                Ok(true)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn rename(&self, id: &str, user: &str, name: &str) -> Result<bool, &str> {
+        let mut chn = self.check_role(id, user, Role::Admin)?;
+        if name.is_empty() {
+            return Err("频道名不能为空");
+        }
+        chn.name = name.to_string();
+        self.save_channel(&chn);
+        Ok(true)
+    }
+
+    // 简介为空表示清除
+    pub fn describe(&self, id: &str, user: &str, description: &str) -> Result<bool, &str> {
+        let mut chn = self.check_role(id, user, Role::Admin)?;
+        chn.description = description.to_string();
+        self.save_channel(&chn);
+        Ok(true)
+    }
+
+    // 设置handle，为空表示清除
+    pub fn set_handle(&self, id: &str, user: &str, handle: &str) -> Result<bool, &str> {
+        self.check_role(id, user, Role::Admin)?;
+        if !handle.is_empty() {
+            check_handle(handle)?;
+        }
+        // 检查和占用handle在同一个事务中完成
+        let env = self.storage.env.read().unwrap();
+        let mut writer = env.write().unwrap();
+        let mut chn: Channel = match self.storage.get_json(&writer, id) {
+            Some(channel_string) => serde_json::from_str(&channel_string).unwrap(),
+            None => return Err("没找到对应频道"),
+        };
+        if !handle.is_empty() {
+            match self.storage_handle.get_json(&writer, handle) {
+                Some(ref other) if other != &chn.id => return Err("handle已被使用"),
+                _ => (),
+            }
+        }
+        self.release_handle(&mut writer, &chn);
+        if !handle.is_empty() {
+            self.storage_handle.put_json(&mut writer, handle, &chn.id);
+        }
+        chn.handle = handle.to_string();
+        self.storage
+            .put_json(&mut writer, &chn.id, &serde_json::to_string(&chn).unwrap());
+        writer.commit().unwrap();
+        Ok(true)
+    }
+
+    // handle仍然指向该频道时才删除，避免误删其他频道已经占用的handle
+    fn release_handle(&self, writer: &mut rkv::Writer, chn: &Channel) {
+        if !chn.handle.is_empty()
+            && self.storage_handle.get_json(writer, &chn.handle).as_deref() == Some(chn.id.as_str())
+        {
+            self.storage_handle.del_json(writer, &chn.handle);
+        }
+    }
+
+    // 把handle转换为频道id，不是handle时原样返回
+    pub fn resolve(&self, id_or_handle: &str) -> String {
+        if id_or_handle.len() <= HANDLE_MAX_LEN {
+            if let Some(id) = self.storage_handle.get_single(id_or_handle) {
+                return id;
+            }
+        }
+        id_or_handle.to_string()
+    }
+
+    pub fn set_listed(&self, id: &str, user: &str, listed: bool) -> Result<bool, &str> {
+        let mut chn = self.check_role(id, user, Role::Admin)?;
+        chn.listed = listed;
+        self.save_channel(&chn);
+        Ok(true)
+    }
+
+    // 在频道目录中按名称和简介搜索，订阅人数多的在前
+    pub fn search(&self, keyword: &str) -> Vec<Channel> {
+        let keyword = keyword.to_lowercase();
+        let env = self.storage.env.read().unwrap();
+        let reader = env.read().unwrap();
+        let mut iter = self.storage.single.iter_start(&reader).unwrap();
+        let mut ret = Vec::<Channel>::new();
+        while let Some(Ok((_, channel))) = iter.next() {
+            if let Some(rkv::Value::Json(_channel)) = channel {
+                let chn: Channel = serde_json::from_str(_channel).unwrap();
+                if chn.listed
+                    && (chn.name.to_lowercase().contains(&keyword)
+                        || chn.description.to_lowercase().contains(&keyword))
+                {
+                    ret.push(chn);
+                }
+            }
+        }
+        ret.sort_by_key(|chn| std::cmp::Reverse(chn.subscribers.len()));
+        ret
+    }
+
+    // 获取二维码，还没有生成或者已经过期时为空
+    fn get_qrcode(&self, key: &str) -> String {
+        match self.storage_qrcode.get_single(key) {
+            Some(qrcode_string) => {
+                let qrcode: Qrcode = serde_json::from_str(&qrcode_string).unwrap();
+                if qrcode.expires > chrono::Local::now().timestamp() {
+                    qrcode.url
+                } else {
+                    String::new()
+                }
+            }
+            None => String::new(),
+        }
+    }
+
+    pub fn subscribe_qrcode(&self, id: &str) -> String {
+        self.get_qrcode(id)
+    }
+
+    // 邀请码二维码以场景值为key，和频道id区分
+    pub fn invite_qrcode(&self, code: &str) -> String {
+        self.get_qrcode(&format!("{}{}", INVITE_SCENE_PREFIX, code))
+    }
+
+    // 后台任务调用，为目录中的频道和有效的邀请码生成快过期的二维码
+    pub fn refresh_qrcodes(&self) {
+        let now = chrono::Local::now().timestamp();
+        for chn in self.search("") {
+            let scene = format!("{}{}", SUBSCRIBE_SCENE_PREFIX, chn.id);
+            self.refresh_qrcode(&chn.id, &scene, 0, now);
+        }
+        let mut invite_keys = Vec::new();
+        for invite in self.get_all_invites() {
+            if invite.is_valid() {
+                let scene = format!("{}{}", INVITE_SCENE_PREFIX, invite.code);
+                self.refresh_qrcode(&scene, &scene, invite.expires, now);
+                invite_keys.push(scene);
+            }
+        }
+        // 删除已经失效的邀请码的二维码
+        let mut to_delete = Vec::new();
+        {
+            let env = self.storage_qrcode.env.read().unwrap();
+            let reader = env.read().unwrap();
+            let mut iter = self.storage_qrcode.single.iter_start(&reader).unwrap();
+            while let Some(Ok((key, _))) = iter.next() {
+                let key = std::str::from_utf8(key).unwrap().to_string();
+                if key.starts_with(INVITE_SCENE_PREFIX) && !invite_keys.contains(&key) {
+                    to_delete.push(key);
+                }
+            }
+        }
+        for key in to_delete {
+            self.storage_qrcode.del_single(&key);
+        }
+    }
+
+    // 二维码需要有效到until，0表示一直需要，快过期时重新生成
+    fn refresh_qrcode(&self, key: &str, scene: &str, until: i64, now: i64) {
+        let old: Option<Qrcode> = self
+            .storage_qrcode
+            .get_single(key)
+            .map(|qrcode_string| serde_json::from_str(&qrcode_string).unwrap());
+        if matches!(old, Some(ref qrcode) if qrcode.refresh_at > now) {
+            return;
+        }
+        let max_expire = super::wx_interface::QRCODE_MAX_EXPIRE;
+        let expire = if until > 0 {
+            (until - now).min(max_expire)
+        } else {
+            max_expire
+        };
+        let qrcode = match super::wx_interface::INTERFACE.create_qrcode(scene, expire) {
+            Ok(url) => Qrcode {
+                url,
+                expires: now + expire,
+                // 能有效到until的二维码不用再生成
+                refresh_at: if until > 0 && now + expire >= until {
+                    until
+                } else {
+                    now + expire - QRCODE_REFRESH_BEFORE
+                },
+            },
+            Err(err) => {
+                debug!("qrcode {}:{}", scene, err);
+                // 保留旧的二维码，稍后重试
+                let mut qrcode = old.unwrap_or(Qrcode {
+                    url: String::new(),
+                    expires: 0,
+                    refresh_at: 0,
+                });
+                qrcode.refresh_at = now + QRCODE_RETRY;
+                qrcode
+            }
+        };
+        let json_string = serde_json::to_string(&qrcode).unwrap();
+        self.storage_qrcode
+            .put_single(key, &rkv::Value::Json(&json_string));
+    }
+
+    pub fn set_visibility(
+        &self,
+        id: &str,
+        owner: &str,
+        visibility: Visibility,
+    ) -> Result<bool, &str> {
+        let mut chn = self.check_role(id, owner, Role::Admin)?;
+        chn.visibility = visibility;
+        self.save_channel(&chn);
+        Ok(true)
+    }
+
+    fn get_all_requests(&self) -> Vec<SubscribeRequest> {
+        let env = self.storage_request.env.read().unwrap();
+        let reader = env.read().unwrap();
+        let mut iter = self.storage_request.single.iter_start(&reader).unwrap();
+        let mut ret = Vec::new();
+        while let Some(Ok((_, request))) = iter.next() {
+            if let Some(rkv::Value::Json(request)) = request {
+                ret.push(serde_json::from_str(request).unwrap());
+            }
+        }
+        ret
+    }
+
+    fn get_requests_by_channel(&self, channel: &str) -> Vec<SubscribeRequest> {
+        self.get_all_requests()
+            .into_iter()
+            .filter(|request| request.channel == channel)
+            .collect()
+    }
+
+    // 创建订阅申请并通知频道创建者
+    fn add_request(&self, chn: &Channel, user: &str, tags: &[String]) -> Result<String, &str> {
+        if self
+            .get_requests_by_channel(&chn.id)
+            .iter()
+            .any(|request| request.user == user)
+        {
+            return Err("已经提交过订阅申请，请等待审核");
+        }
+        let id = uuid::Uuid::new_v4().to_simple().to_string();
+        let request = SubscribeRequest {
+            id: id.clone(),
+            channel: chn.id.clone(),
+            user: user.to_string(),
+            time: chrono::Local::now().timestamp(),
+            tags: tags.to_vec(),
+        };
+        debug!("new subscribe request:{:?}", request);
+        let json_string = serde_json::to_string(&request).unwrap();
+        self.storage_request
+            .put_single(&id, &rkv::Value::Json(&json_string));
+        let name = match super::user::INTERFACE.get_user(user) {
+            Ok(usr) => usr.name,
+            Err(_) => user.to_string(),
+        };
+        let text = format!(
+                "{}申请订阅频道{}\n<a href=\"weixin://bizmsgmenu?msgmenucontent=approve%20{}&msgmenuid=1\">同意</a> <a href=\"weixin://bizmsgmenu?msgmenucontent=reject%20{}&msgmenuid=2\">拒绝</a>",
+                name, chn.name, id, id
+            );
+        // 通知创建者和所有管理员
+        for manager in std::iter::once(&chn.owner).chain(chn.admins.iter()) {
+            super::wx_interface::send_text_background(manager, &text);
+        }
+        Ok(id)
+    }
+
+    // 获取用户创建的频道的所有订阅申请
+    pub fn get_requests(&self, owner: &str) -> Vec<SubscribeRequest> {
+        let mut ret: Vec<SubscribeRequest> = self
+            .get_all_requests()
+            .into_iter()
+            .filter(|request| {
+                self.get_channel_by_id(&request.channel)
+                    .map(|chn| chn.is_manager(owner))
+                    .unwrap_or(false)
+            })
+            .collect();
+        ret.sort_by_key(|request| request.time);
+        ret
+    }
+
+    // 处理订阅申请，通过时添加订阅者，并通知申请人
+    pub fn review_request(&self, id: &str, owner: &str, approve: bool) -> Result<bool, &str> {
+        let request: SubscribeRequest = match self.storage_request.get_single(id) {
+            Some(request_string) => serde_json::from_str(&request_string).unwrap(),
+            None => return Err("没找到对应的订阅申请"),
+        };
+        let chn = self.check_role(&request.channel, owner, Role::Admin)?;
+        // 添加失败时保留申请，可以稍后重新审核或者拒绝
+        let text = if approve {
+            match self.add_subscriber(&chn.id, &request.user) {
+                Ok(_) | Err("已经订阅过了") => {
+                    if !request.tags.is_empty() {
+                        let tags = request.tags.clone();
+                        super::user::INTERFACE.set_subscribe_option(
+                            &request.user,
+                            &chn.id,
+                            |option| option.tags = tags,
+                        )?;
+                    }
+                    format!("频道{}已通过你的订阅申请", chn.name)
+                }
+                Err(err) => return Err(err),
+            }
+        } else {
+            format!("频道{}拒绝了你的订阅申请", chn.name)
+        };
+        self.storage_request.del_single(id);
+        super::wx_interface::send_text_background(&request.user, &text);
+        Ok(true)
+    }
+
+    fn get_invite(&self, code: &str) -> Option<Invite> {
+        self.storage_invite
+            .get_single(code)
+            .map(|invite_string| serde_json::from_str(&invite_string).unwrap())
+    }
+
+    fn put_invite(&self, invite: &Invite) {
+        let json_string = serde_json::to_string(invite).unwrap();
+        self.storage_invite
+            .put_single(&invite.code, &rkv::Value::Json(&json_string));
+    }
+
+    // 删除前重新检查，避免删掉刚归还了次数的邀请码
+    fn del_invalid_invite(&self, code: &str) {
+        self.storage_invite.update_single(code, |value| {
+            if let Some(invite_string) = value {
+                let invite: Invite = serde_json::from_str(invite_string).unwrap();
+                if !invite.is_valid() {
+                    *value = None;
+                }
+            }
+        });
+    }
+
+    fn get_all_invites(&self) -> Vec<Invite> {
+        let env = self.storage_invite.env.read().unwrap();
+        let reader = env.read().unwrap();
+        let mut iter = self.storage_invite.single.iter_start(&reader).unwrap();
+        let mut ret = Vec::new();
+        while let Some(Ok((_, invite))) = iter.next() {
+            if let Some(rkv::Value::Json(invite)) = invite {
+                ret.push(serde_json::from_str(invite).unwrap());
+            }
+        }
+        ret
+    }
+
+    fn get_invites_by_channel(&self, channel: &str) -> Vec<Invite> {
+        self.get_all_invites()
+            .into_iter()
+            .filter(|invite| invite.channel == channel)
+            .collect()
+    }
+
+    // 新增邀请码，max_uses和expire_days为0表示不限
+    pub fn add_invite(
+        &self,
+        id: &str,
+        owner: &str,
+        max_uses: u32,
+        expire_days: i64,
+    ) -> Result<Invite, &str> {
+        let chn = self.check_role(id, owner, Role::Admin)?;
+        let now = chrono::Local::now().timestamp();
+        // 邀请码需要手动输入，只取uuid前8位
+        let mut code = uuid::Uuid::new_v4().to_simple().to_string()[..8].to_string();
+        while self.get_invite(&code).is_some() {
+            code = uuid::Uuid::new_v4().to_simple().to_string()[..8].to_string();
+        }
+        let expires = if expire_days > 0 {
+            match expire_days
+                .checked_mul(24 * 3600)
+                .and_then(|secs| now.checked_add(secs))
+            {
+                Some(expires) => expires,
+                None => return Err("格式不对"),
+            }
+        } else {
+            0
+        };
+        // 二维码由后台任务生成
+        let invite = Invite {
+            code,
+            channel: chn.id,
+            max_uses,
+            uses: 0,
+            expires,
+            created: now,
+        };
+        debug!("new invite:{:?}", invite);
+        self.put_invite(&invite);
+        Ok(invite)
+    }
+
+    // 获取还有效的邀请码，同时删除失效的
+    pub fn get_invites(&self, id: &str, owner: &str) -> Result<Vec<Invite>, &str> {
+        let chn = self.check_role(id, owner, Role::Admin)?;
+        let (mut valid, invalid): (Vec<Invite>, Vec<Invite>) = self
+            .get_invites_by_channel(&chn.id)
+            .into_iter()
+            .partition(|invite| invite.is_valid());
+        for invite in invalid {
+            self.del_invalid_invite(&invite.code);
+        }
+        valid.sort_by_key(|invite| invite.created);
+        Ok(valid)
+    }
+
+    pub fn revoke_invite(&self, id: &str, owner: &str, code: &str) -> Result<bool, &str> {
+        let chn = self.check_role(id, owner, Role::Admin)?;
+        match self.get_invite(code) {
+            Some(ref invite) if invite.channel == chn.id => {
+                self.storage_invite.del_single(code);
+                Ok(true)
+            }
+            _ => Err("没找到对应的邀请码"),
+        }
+    }
+
+    // 使用邀请码订阅频道，不受订阅方式限制
+    pub fn redeem_invite(&self, code: &str, user: &str) -> Result<Channel, &str> {
+        // 在同一个写事务中检查并占用一次使用次数，避免同时使用时超过限制
+        let channel = self.storage_invite.update_single(code, |value| {
+            let mut invite: Invite = match value {
+                Some(invite_string) => serde_json::from_str(invite_string).unwrap(),
+                None => return Err("邀请码无效"),
+            };
+            if !invite.is_valid() {
+                // 次数用完的可能还有其他用户在订阅中，等订阅成功后再删除
+                if invite.expires > 0 && invite.expires <= chrono::Local::now().timestamp() {
+                    *value = None;
+                }
+                return Err("邀请码已失效");
+            }
+            invite.uses += 1;
+            *value = Some(serde_json::to_string(&invite).unwrap());
+            Ok(invite.channel)
+        })?;
+        match self
+            .get_channel_by_id(&channel)
+            .and_then(|chn| self.add_subscriber(&chn.id, user).map(|_| chn))
+        {
+            Ok(chn) => {
+                // 用完的邀请码直接删除
+                self.del_invalid_invite(code);
+                Ok(chn)
+            }
+            Err(err) => {
+                // 订阅失败时归还占用的次数
+                self.storage_invite.update_single(code, |value| {
+                    if let Some(invite_string) = value {
+                        let mut invite: Invite = serde_json::from_str(invite_string).unwrap();
+                        invite.uses = invite.uses.saturating_sub(1);
+                        *value = Some(serde_json::to_string(&invite).unwrap());
+                    }
+                });
+                Err(err)
+            }
+        }
+    }
+
+    // 获取订阅源凭证，没有或者要求重置时重新生成
+    pub fn feed_token(&self, id: &str, owner: &str, reset: bool) -> Result<String, &str> {
+        match self.check_role(id, owner, Role::Admin) {
+            Ok(mut chn) => {
+                if reset || chn.feed_token.is_empty() {
+                    chn.feed_token = uuid::Uuid::new_v4().to_simple().to_string();
+                    self.save_channel(&chn);
+                }
+                Ok(chn.feed_token)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // 生成新的sendkey，旧的sendkey在宽限期内仍然可用，返回新的sendkey
+    pub fn reset_sendkey(&self, id: &str, owner: &str, grace_minutes: i64) -> Result<String, &str> {
+        match self.check_role(id, owner, Role::Admin) {
+            Ok(mut chn) => {
+                self.rotate_sendkey(&mut chn, grace_minutes)?;
+                Ok(chn.sendkey)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // 管理接口使用，不检查创建者
+    pub fn admin_reset_sendkey(&self, id: &str, grace_minutes: i64) -> Result<String, &str> {
+        match self.get_channel_by_id(id) {
+            Ok(mut chn) => {
+                self.rotate_sendkey(&mut chn, grace_minutes)?;
+                Ok(chn.sendkey)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn rotate_sendkey(&self, chn: &mut Channel, grace_minutes: i64) -> Result<(), &str> {
+        if grace_minutes > GRACE_MAX_MINUTES {
+            return Err("宽限期太长");
+        }
+        let now = chrono::Local::now().timestamp();
+        chn.ensure_default_key();
+        self.clean_expired_keys(chn);
+        let new_key = SendKey::new(DEFAULT_KEY_LABEL, 0, Vec::new());
+        let old_sendkey = std::mem::replace(&mut chn.sendkey, new_key.key.clone());
+        if grace_minutes > 0 {
+            // 旧的默认sendkey改名保留到宽限期结束
+            if let Some(key) = chn.keys.iter_mut().find(|key| key.key == old_sendkey) {
+                key.label = format!("{}-{}", DEFAULT_KEY_LABEL, now);
+                key.expires = now + grace_minutes * 60;
+            }
+        } else {
+            chn.keys.retain(|key| key.key != old_sendkey);
+            self.storage_sendkey.del_single(&old_sendkey);
+        }
+        self.storage_sendkey
+            .put_single(&new_key.key, &rkv::Value::Json(&chn.id));
+        chn.keys.insert(0, new_key);
+        self.save_channel(chn);
+        Ok(())
+    }
+
+    fn clean_expired_keys(&self, chn: &mut Channel) {
+        let (expired, keys): (Vec<_>, Vec<_>) =
+            chn.keys.drain(..).partition(|key| key.is_expired());
+        chn.keys = keys;
+        for key in expired {
+            self.storage_sendkey.del_single(&key.key);
+        }
+    }
+
+    // 检查用户在频道中的角色是否不低于要求的角色
+    pub fn check_role(&self, id: &str, user: &str, role: Role) -> Result<Channel, &str> {
+        match self.get_channel_by_id(id) {
+            Ok(mut chn) => {
+                if !matches!(chn.role_of(user), Some(r) if r >= role) {
+                    return match role {
+                        Role::Owner => Err("不是频道的创建者"),
+                        Role::Admin => Err("没有管理频道的权限"),
+                        Role::Member => Err("没有订阅该频道"),
+                    };
+                }
+                chn.ensure_default_key();
+                Ok(chn)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // 任免管理员，只有创建者可以操作
+    pub fn set_admin(&self, id: &str, owner: &str, user: &str, admin: bool) -> Result<bool, &str> {
+        let mut chn = self.check_role(id, owner, Role::Owner)?;
+        if user == chn.owner {
+            return Err("不能修改创建者的角色");
+        }
+        let position = chn.admins.iter().position(|usr| usr == user);
+        match (admin, position) {
+            (true, Some(_)) => return Err("该用户已经是管理员"),
+            (true, None) => {
+                super::user::INTERFACE.get_user(user)?;
+                chn.admins.push(user.to_string());
+            }
+            (false, Some(i)) => {
+                chn.admins.remove(i);
+            }
+            (false, None) => return Err("该用户不是管理员"),
+        }
+        self.save_channel(&chn);
+        Ok(true)
+    }
+
+    pub fn get_keys(&self, id: &str, owner: &str) -> Result<Vec<SendKey>, &str> {
+        match self.check_role(id, owner, Role::Admin) {
+            Ok(chn) => Ok(chn.keys),
+            Err(err) => Err(err),
+        }
+    }
+
+    // 新增sendkey，expire_days为0表示不过期，返回新的sendkey
+    pub fn add_key(
+        &self,
+        id: &str,
+        owner: &str,
+        label: &str,
+        expire_days: i64,
+        scopes: Vec<String>,
+    ) -> Result<String, &str> {
+        let mut chn = self.check_role(id, owner, Role::Admin)?;
+        self.clean_expired_keys(&mut chn);
+        if chn.keys.iter().any(|key| key.label == label) {
+            return Err("名称已存在");
+        }
+        if scopes.iter().any(|scope| !SCOPES.contains(&scope.as_str())) {
+            return Err("权限不对");
+        }
+        let expires = if expire_days > 0 {
+            match expire_days
+                .checked_mul(24 * 3600)
+                .and_then(|secs| chrono::Local::now().timestamp().checked_add(secs))
+            {
+                Some(expires) => expires,
+                None => return Err("格式不对"),
+            }
+        } else {
+            0
+        };
+        let key = SendKey::new(label, expires, scopes);
+        let sendkey = key.key.clone();
+        self.storage_sendkey
+            .put_single(&sendkey, &rkv::Value::Json(&chn.id));
+        chn.keys.push(key);
+        self.save_channel(&chn);
+        Ok(sendkey)
+    }
+
+    pub fn revoke_key(&self, id: &str, owner: &str, label: &str) -> Result<bool, &str> {
+        let mut chn = self.check_role(id, owner, Role::Admin)?;
+        match chn.keys.iter().position(|key| key.label == label) {
+            Some(i) => {
+                if chn.keys[i].key == chn.sendkey {
+                    return Err("默认SendKey只能重置");
+                }
+                let key = chn.keys.remove(i);
+                self.storage_sendkey.del_single(&key.key);
+                self.save_channel(&chn);
+                Ok(true)
+            }
+            None => Err("没找到对应的SendKey"),
+        }
+    }
+
+    // 检查sendkey是否允许对应操作，返回频道和sendkey名称，同时记录使用时间
+    pub fn authorize_sendkey(&self, sendkey: &str, scope: &str) -> Result<(Channel, String), &str> {
+        let mut chn = self.get_channel_by_sendkey(sendkey)?;
+        chn.ensure_default_key();
+        let label = match chn.keys.iter_mut().find(|key| key.key == sendkey) {
+            Some(key) => {
+                if !key.allows(scope) {
+                    return Err("SendKey没有权限");
+                }
+                key.last_used = chrono::Local::now().timestamp();
+                key.label.clone()
+            }
+            None => return Err("没找到对应的频道"),
+        };
+        // 只在写事务中更新last_used，避免覆盖同时发生的其他修改
+        self.storage.update_single(&chn.id, |value| {
+            if let Some(channel_string) = value {
+                let mut latest: Channel = serde_json::from_str(channel_string).unwrap();
+                latest.ensure_default_key();
+                if let Some(key) = latest.keys.iter_mut().find(|key| key.key == sendkey) {
+                    key.last_used = chrono::Local::now().timestamp();
+                }
+                *value = Some(serde_json::to_string(&latest).unwrap());
+            }
+        });
+        Ok((chn, label))
+    }
+
+    fn save_channel(&self, chn: &Channel) {
+        let json_string = serde_json::to_string(chn).unwrap();
+        self.storage
+            .put_single(&chn.id, &rkv::Value::Json(&json_string));
+    }
+
     pub fn get_channel_by_id(&self, id: &str) -> Result<Channel, &str> {
         let channel = self.storage.get_single(id);
         match channel {
@@ -112,7 +1192,73 @@ impl ChannelInterface {
             None => Err("没找到对应频道"),
         }
     }
-    pub fn get_channel_by_owner(&self, user: &str) -> Result<Vec<Channel>, &str> {
+    // 发起转让，需要接收人确认后才生效
+    pub fn transfer(&self, id: &str, owner: &str, user: &str) -> Result<bool, &str> {
+        let mut chn = self.check_role(id, owner, Role::Owner)?;
+        if user == owner {
+            return Err("不能转让给自己");
+        }
+        super::user::INTERFACE.get_user(user)?;
+        chn.pending_owner = Some(user.to_string());
+        self.save_channel(&chn);
+        let name = match super::user::INTERFACE.get_user(owner) {
+            Ok(usr) => usr.name,
+            Err(_) => owner.to_string(),
+        };
+        super::wx_interface::send_text_background(
+            user,
+            &format!(
+                "{}想把频道{}转让给你\n<a href=\"weixin://bizmsgmenu?msgmenucontent=accept%20transfer%20{}&msgmenuid=1\">接受</a> <a href=\"weixin://bizmsgmenu?msgmenucontent=decline%20transfer%20{}&msgmenuid=2\">拒绝</a>",
+                name, chn.name, chn.id, chn.id
+            ),
+        );
+        Ok(true)
+    }
+
+    // 取消还没有确认的转让
+    pub fn cancel_transfer(&self, id: &str, owner: &str) -> Result<bool, &str> {
+        let mut chn = self.check_role(id, owner, Role::Owner)?;
+        if chn.pending_owner.take().is_none() {
+            return Err("没有等待确认的转让");
+        }
+        self.save_channel(&chn);
+        Ok(true)
+    }
+
+    // 接收人确认或者拒绝转让，并通知原创建者
+    pub fn confirm_transfer(&self, id: &str, user: &str, accept: bool) -> Result<bool, &str> {
+        // 两个用户和频道在同一个事务中读取和更新
+        let (old_owner, text) = {
+            let env = self.storage.env.read().unwrap();
+            let mut writer = env.write().unwrap();
+            let mut chn: Channel = match self.storage.get_json(&writer, id) {
+                Some(channel_string) => serde_json::from_str(&channel_string).unwrap(),
+                None => return Err("没找到对应频道"),
+            };
+            if chn.pending_owner.as_deref() != Some(user) {
+                return Err("没有等待确认的转让");
+            }
+            chn.pending_owner = None;
+            let old_owner = chn.owner.clone();
+            let text = if accept {
+                super::user::INTERFACE.transfer_channel(&mut writer, &old_owner, user, id)?;
+                chn.owner = user.to_string();
+                chn.admins.retain(|usr| usr != user);
+                format!("频道{}已经转让成功", chn.name)
+            } else {
+                format!("对方拒绝了频道{}的转让", chn.name)
+            };
+            self.storage
+                .put_json(&mut writer, id, &serde_json::to_string(&chn).unwrap());
+            writer.commit().unwrap();
+            (old_owner, text)
+        };
+        super::wx_interface::send_text_background(&old_owner, &text);
+        Ok(true)
+    }
+
+    // 获取用户创建或者管理的频道
+    pub fn get_channel_by_manager(&self, user: &str) -> Result<Vec<Channel>, &str> {
         let env = self.storage.env.read().unwrap();
         let reader = env.read().unwrap();
         let mut iter = self.storage.single.iter_start(&reader).unwrap();
@@ -121,7 +1267,7 @@ impl ChannelInterface {
             if let Some(rkv::Value::Json(_channel)) = channel {
                 let chn: Channel = serde_json::from_str(_channel).unwrap();
                 debug!("{}, {:?}", std::str::from_utf8(&id).unwrap(), chn);
-                if chn.owner == user {
+                if chn.is_manager(user) {
                     ret.push(chn);
                 }
             }
@@ -130,19 +1276,45 @@ impl ChannelInterface {
     }
 
     pub fn get_channel_by_sendkey(&self, sendkey: &str) -> Result<Channel, &str> {
-        let env = self.storage.env.read().unwrap();
-        let reader = env.read().unwrap();
-        let mut iter = self.storage.single.iter_start(&reader).unwrap();
-        while let Some(Ok((id, channel))) = iter.next() {
-            if let Some(rkv::Value::Json(_channel)) = channel {
-                let chn: Channel = serde_json::from_str(_channel).unwrap();
-                debug!("{}, {:?}", std::str::from_utf8(&id).unwrap(), chn);
-                if chn.sendkey == sendkey {
+        // 先查索引
+        if let Some(id) = self.storage_sendkey.get_single(sendkey) {
+            if let Ok(mut chn) = self.get_channel_by_id(&id) {
+                chn.ensure_default_key();
+                if chn
+                    .keys
+                    .iter()
+                    .any(|key| key.key == sendkey && !key.is_expired())
+                {
                     return Ok(chn);
                 }
             }
+            return Err("没找到对应的频道");
+        }
+        // 兼容没有索引的旧数据，找到后补上索引
+        let mut found = None;
+        {
+            let env = self.storage.env.read().unwrap();
+            let reader = env.read().unwrap();
+            let mut iter = self.storage.single.iter_start(&reader).unwrap();
+            while let Some(Ok((id, channel))) = iter.next() {
+                if let Some(rkv::Value::Json(_channel)) = channel {
+                    let chn: Channel = serde_json::from_str(_channel).unwrap();
+                    debug!("{}, {:?}", std::str::from_utf8(&id).unwrap(), chn);
+                    if chn.sendkey == sendkey {
+                        found = Some(chn);
+                        break;
+                    }
+                }
+            }
+        }
+        match found {
+            Some(chn) => {
+                self.storage_sendkey
+                    .put_single(sendkey, &rkv::Value::Json(&chn.id));
+                Ok(chn)
+            }
+            None => Err("没找到对应的频道"),
         }
-        Err("没找到对应的频道")
     }
 
     pub fn get_subscribers(&self, id: &str) -> Result<Vec<super::user::User>, &str> {
@@ -13,6 +13,64 @@ pub struct Config {
     pub detail_template: String,
     pub content_expire: u32,
     pub listen: String,
+    // 单个附件大小上限，单位字节
+    #[serde(default = "default_attachment_max_size")]
+    pub attachment_max_size: usize,
+    // 每条消息附件数量上限
+    #[serde(default = "default_attachment_max_count")]
+    pub attachment_max_count: usize,
+    // 管理接口的访问凭证，留空表示不开启管理接口
+    #[serde(default)]
+    pub admin_token: String,
+    // 发送频率限制，0表示不限制
+    #[serde(default)]
+    pub rate_key_per_minute: u32,
+    #[serde(default)]
+    pub rate_key_per_day: u32,
+    #[serde(default)]
+    pub rate_channel_per_minute: u32,
+    #[serde(default)]
+    pub rate_channel_per_day: u32,
+    // 全账号每日模板消息额度，0表示不限制
+    #[serde(default)]
+    pub template_daily_budget: u32,
+    // 单个频道最多可以使用每日额度的百分比
+    #[serde(default = "default_template_channel_share")]
+    pub template_channel_share: u32,
+    // 同一频道相同内容在这段时间内只推送一次，单位秒，0表示不去重
+    #[serde(default)]
+    pub dedupe_window: i64,
+    // 每日摘要的推送时间，0-23点
+    #[serde(default = "default_digest_daily_hour")]
+    pub digest_daily_hour: u32,
+    // 每个用户最多创建的频道数量，0表示不限制
+    #[serde(default)]
+    pub max_channels_per_owner: usize,
+    // 每个频道最多的订阅人数，0表示不限制
+    #[serde(default)]
+    pub max_subscribers_per_channel: usize,
+    // 每个用户最多订阅的频道数量，0表示不限制
+    #[serde(default)]
+    pub max_subscriptions_per_user: usize,
+    // 不受以上数量限制的用户openid
+    #[serde(default)]
+    pub quota_whitelist: Vec<String>,
+}
+
+fn default_attachment_max_size() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_attachment_max_count() -> usize {
+    5
+}
+
+fn default_template_channel_share() -> u32 {
+    100
+}
+
+fn default_digest_daily_hour() -> u32 {
+    9
 }
 
 impl Config {
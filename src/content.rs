@@ -4,8 +4,70 @@ use uuid;
 
 use std::sync::{Arc, RwLock};
 
+#[derive(Debug, Deserialize, Clone, Serialize, Default)]
+pub struct Content {
+    pub body: String,
+    // 所属频道id
+    #[serde(default)]
+    pub channel: String,
+    #[serde(default)]
+    pub title: String,
+    // 推送时间戳
+    #[serde(default)]
+    pub time: i64,
+    // 附件id
+    #[serde(default)]
+    pub attachments: Vec<String>,
+    // 发送消息的sendkey名称
+    #[serde(default)]
+    pub sender: String,
+    #[serde(default)]
+    pub edited: bool,
+    // 最后修改或撤回的时间戳，0表示没有修改过
+    #[serde(default)]
+    pub updated: i64,
+    // 已撤回的内容不再展示
+    #[serde(default)]
+    pub recalled: bool,
+    // 紧急消息不受免打扰时段限制
+    #[serde(default)]
+    pub urgent: bool,
+    // 频道内的话题标签
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Content {
+    // 列表中展示的标题，撤回后不再显示原标题
+    pub fn display_title(&self) -> &str {
+        if self.recalled {
+            "消息已撤回"
+        } else {
+            &self.title
+        }
+    }
+
+    // 订阅源中的更新时间
+    pub fn updated_at(&self) -> i64 {
+        self.updated.max(self.time)
+    }
+}
+
+// 解析逗号分隔的标签，去掉空白和重复
+pub fn parse_tags(tags: &str) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    for tag in tags.split(',') {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !ret.contains(&tag) {
+            ret.push(tag);
+        }
+    }
+    ret
+}
+
 const STORE: &str = "content";
 const STORE_INDEX: &str = "content_index";
+const STORE_CHANNEL: &str = "content_channel";
 
 lazy_static! {
     pub static ref INTERFACE: ContentInterface = ContentInterface::new();
@@ -16,6 +78,8 @@ pub struct ContentInterface {
     storage: super::storage::SingleKvStorage,
     // 按照日期存储的 date/vec(id) 索引
     storage_index: super::storage::SingleKvStorage,
+    // 按照频道存储的 channel/vec(id) 索引
+    storage_channel: super::storage::SingleKvStorage,
     // 上次检查过期时间
     last_expire_check: Arc<RwLock<chrono::Date<chrono::Local>>>,
 }
@@ -28,17 +92,23 @@ impl ContentInterface {
                 &super::CONFIG.db_path,
                 STORE_INDEX,
             ),
+            storage_channel: super::storage::SingleKvStorage::new(
+                &super::CONFIG.db_path,
+                STORE_CHANNEL,
+            ),
             last_expire_check: Arc::new(RwLock::new(chrono::Local::today().pred())),
         }
     }
     // 返回内容id
-    pub fn add_content(&self, body: &str) -> String {
+    pub fn add_content(&self, mut content: Content) -> String {
         let id = uuid::Uuid::new_v4().to_simple().to_string();
         let today = chrono::Local::today();
-        debug!("new content id:{},body:{}", id, body);
+        debug!("new content id:{},body:{}", id, content.body);
         let date: String = today.format("%Y%m%d").to_string();
         // 添加内容
-        self.storage.put_single(&id, &rkv::Value::Json(&body));
+        content.time = chrono::Local::now().timestamp();
+        self.put_content(&id, &content);
+        let channel = content.channel.as_str();
         // 添加到索引
         let ids = self.storage_index.get_single(&date);
         let mut new_ids = Vec::new();
@@ -49,6 +119,8 @@ impl ContentInterface {
         let new_json = serde_json::to_string(&new_ids).unwrap();
         self.storage_index
             .put_single(&date, &rkv::Value::Json(&new_json));
+        // 添加到频道索引
+        self.update_channel_ids(channel, |channel_ids| channel_ids.push(id.clone()));
 
         let content = self.storage.get_single(&id);
         debug!("get content:{}", id);
@@ -66,15 +138,104 @@ impl ContentInterface {
         id
     }
 
-    pub fn get_content(&self, id: &str) -> Result<String, &str> {
+    pub fn get_content(&self, id: &str) -> Result<Content, &str> {
         let content = self.storage.get_single(id);
         debug!("get content:{}", id);
         match content {
-            Some(content_string) => Ok(content_string),
+            Some(content_string) => match serde_json::from_str(&content_string) {
+                Ok(content) => Ok(content),
+                // 兼容旧版本直接存储的正文
+                Err(_) => Ok(Content {
+                    body: content_string,
+                    ..Default::default()
+                }),
+            },
             None => Err("没找到对应内容"),
         }
     }
 
+    fn put_content(&self, id: &str, content: &Content) {
+        let json_string = serde_json::to_string(content).unwrap();
+        self.storage.put_single(id, &rkv::Value::Json(&json_string));
+    }
+
+    pub fn update_content(&self, id: &str, body: &str) -> Result<bool, &str> {
+        match self.get_content(id) {
+            Ok(mut content) => {
+                if content.recalled {
+                    return Err("内容已撤回");
+                }
+                content.body = body.to_string();
+                content.edited = true;
+                content.updated = chrono::Local::now().timestamp();
+                self.put_content(id, &content);
+                Ok(true)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn recall_content(&self, id: &str) -> Result<bool, &str> {
+        match self.get_content(id) {
+            Ok(mut content) => {
+                for blob in &content.attachments {
+                    super::blob::INTERFACE.del_blob(blob);
+                }
+                content.body = String::new();
+                content.attachments = Vec::new();
+                content.recalled = true;
+                content.updated = chrono::Local::now().timestamp();
+                self.put_content(id, &content);
+                Ok(true)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn get_channel_ids(&self, channel: &str) -> Vec<String> {
+        match self.storage_channel.get_single(channel) {
+            Some(ids_string) => serde_json::from_str(&ids_string).unwrap(),
+            None => Vec::new(),
+        }
+    }
+
+    // 在同一个写事务中读取和修改频道索引，避免并发推送时丢失id
+    fn update_channel_ids(&self, channel: &str, f: impl FnOnce(&mut Vec<String>)) {
+        self.storage_channel.update_single(channel, |value| {
+            let mut ids: Vec<String> = match value {
+                Some(ids_string) => serde_json::from_str(ids_string).unwrap(),
+                None => Vec::new(),
+            };
+            f(&mut ids);
+            *value = if ids.is_empty() {
+                None
+            } else {
+                Some(serde_json::to_string(&ids).unwrap())
+            };
+        });
+    }
+
+    // 按时间倒序获取频道的内容，返回 (总数, vec(id, 内容))
+    pub fn get_channel_contents(
+        &self,
+        channel: &str,
+        offset: usize,
+        limit: usize,
+    ) -> (usize, Vec<(String, Content)>) {
+        let ids = self.get_channel_ids(channel);
+        let ret = ids
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(limit)
+            .filter_map(|id| match self.get_content(id) {
+                Ok(content) => Some((id.clone(), content)),
+                Err(_) => None,
+            })
+            .collect();
+        (ids.len(), ret)
+    }
+
     pub fn clean_contents(&self) {
         let expire = super::CONFIG.content_expire;
         debug!("expire:{}", expire);
@@ -97,31 +258,52 @@ impl ContentInterface {
         let cmp_day = today.checked_sub_signed(dur).unwrap();
         let cmp_num = cmp_day.format("%Y%m%d").to_string().parse::<u32>().unwrap();
         // 遍历storage_index查过期内容
-        let env = self.storage_index.env.read().unwrap();
-        let reader = env.read().unwrap();
-        let mut iter = self.storage_index.single.iter_start(&reader).unwrap();
         let mut index_to_delete = Vec::new();
-        while let Some(Ok((date, id))) = iter.next() {
-            let date_string = std::str::from_utf8(&date).unwrap();
-            let date_num = date_string.parse::<u32>().unwrap();
-            debug!("date_num:{},cmp_num:{}", date_num, cmp_num);
-            if date_num >= cmp_num {
-                continue;
+        let mut content_to_delete = Vec::new();
+        {
+            let env = self.storage_index.env.read().unwrap();
+            let reader = env.read().unwrap();
+            let mut iter = self.storage_index.single.iter_start(&reader).unwrap();
+            while let Some(Ok((date, id))) = iter.next() {
+                let date_string = std::str::from_utf8(&date).unwrap();
+                let date_num = date_string.parse::<u32>().unwrap();
+                debug!("date_num:{},cmp_num:{}", date_num, cmp_num);
+                if date_num >= cmp_num {
+                    continue;
+                }
+                if let Some(rkv::Value::Json(ids)) = id {
+                    let _ids: Vec<String> = serde_json::from_str(ids).unwrap();
+                    content_to_delete.extend(_ids);
+                }
+                // 删除自己
+                index_to_delete.push(date_string.to_string());
             }
-            if let Some(rkv::Value::Json(ids)) = id {
-                let _ids: Vec<String> = serde_json::from_str(ids).unwrap();
-                for _id in _ids {
-                    // 从storage删除数据
-                    debug!("del date:{}, id:{}", date_string, _id);
-                    self.storage.del_single(&_id);
+        }
+        let mut channel_to_clean = std::collections::HashMap::<String, Vec<String>>::new();
+        for _id in content_to_delete {
+            // 从storage删除数据
+            debug!("del id:{}", _id);
+            if let Ok(content) = self.get_content(&_id) {
+                // 附件和内容一起过期
+                for blob in &content.attachments {
+                    super::blob::INTERFACE.del_blob(blob);
                 }
+                channel_to_clean
+                    .entry(content.channel)
+                    .or_default()
+                    .push(_id.clone());
             }
-            // 删除自己
-            index_to_delete.push(date_string);
+            self.storage.del_single(&_id);
         }
         for _date in index_to_delete {
             debug!("del index date:{}", _date);
             self.storage_index.del_single(&_date);
         }
+        // 清理频道索引
+        for (channel, ids) in channel_to_clean {
+            self.update_channel_ids(&channel, |channel_ids| {
+                channel_ids.retain(|id| !ids.contains(id))
+            });
+        }
     }
 }
@@ -0,0 +1,118 @@
+use chrono::{Datelike, Duration, Local, TimeZone, Timelike};
+
+// 标准5段cron表达式: 分 时 日 月 周
+#[derive(Debug, Clone)]
+pub struct CronExpr {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    // 日和周都有限制时满足其一即可
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+// 解析单个字段，支持 * , - /
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>, &'static str> {
+    let mut ret = vec![false; (max + 1) as usize];
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => match part[i + 1..].parse::<u32>() {
+                Ok(step) if step > 0 && step <= max => (&part[..i], step),
+                _ => return Err("cron表达式不对"),
+            },
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else {
+            match range.find('-') {
+                Some(i) => match (range[..i].parse::<u32>(), range[i + 1..].parse::<u32>()) {
+                    (Ok(start), Ok(end)) => (start, end),
+                    _ => return Err("cron表达式不对"),
+                },
+                None => match range.parse::<u32>() {
+                    // 单个数字带步长时表示从这个数字开始
+                    Ok(start) if part.contains('/') => (start, max),
+                    Ok(start) => (start, start),
+                    Err(_) => return Err("cron表达式不对"),
+                },
+            }
+        };
+        if start < min || end > max || start > end {
+            return Err("cron表达式不对");
+        }
+        let mut i = start;
+        while i <= end {
+            ret[i as usize] = true;
+            i += step;
+        }
+    }
+    Ok(ret)
+}
+
+impl CronExpr {
+    pub fn parse(expr: &str) -> Result<CronExpr, &'static str> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err("cron表达式不对");
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // 0和7都表示周日
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        Ok(CronExpr {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            days_restricted: fields[2] != "*",
+            weekdays_restricted: fields[4] != "*",
+        })
+    }
+
+    fn day_matches<T: Datelike>(&self, date: &T) -> bool {
+        let day = self.days[date.day() as usize];
+        let weekday = self.weekdays[date.weekday().num_days_from_sunday() as usize];
+        if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    // 计算给定时间之后的下一次执行时间
+    pub fn next_after(&self, timestamp: i64) -> Option<i64> {
+        let mut time = Local.timestamp(timestamp - timestamp % 60 + 60, 0);
+        // 最多向后找4年
+        let limit = time + Duration::days(366 * 4);
+        while time < limit {
+            if !self.months[time.month() as usize] {
+                let (year, month) = if time.month() == 12 {
+                    (time.year() + 1, 1)
+                } else {
+                    (time.year(), time.month() + 1)
+                };
+                time = Local.ymd(year, month, 1).and_hms(0, 0, 0);
+                continue;
+            }
+            if !self.day_matches(&time) {
+                time = (time.date() + Duration::days(1)).and_hms(0, 0, 0);
+                continue;
+            }
+            if !self.hours[time.hour() as usize] {
+                time = time.with_minute(0).unwrap() + Duration::hours(1);
+                continue;
+            }
+            if !self.minutes[time.minute() as usize] {
+                time = time + Duration::minutes(1);
+                continue;
+            }
+            return Some(time.timestamp());
+        }
+        None
+    }
+}
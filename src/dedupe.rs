@@ -0,0 +1,181 @@
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+
+use std::sync::RwLock;
+
+#[derive(Debug, Deserialize, Clone, Serialize)]
+struct Record {
+    // 内容id
+    id: String,
+    time: i64,
+}
+
+const STORE: &str = "dedupe";
+// 清理间隔，单位秒
+const CLEAN_INTERVAL: i64 = 3600;
+// 占用后一直没有写入内容id的记录超过这个时间视为失效，单位秒
+const RESERVE_TIMEOUT: i64 = 60;
+
+lazy_static! {
+    pub static ref INTERFACE: DedupeInterface = DedupeInterface::new();
+}
+
+pub struct DedupeInterface {
+    // 频道和幂等键或者消息摘要 key/record
+    storage: super::storage::SingleKvStorage,
+    // 上次清理时间
+    last_clean: RwLock<i64>,
+}
+
+fn idempotency_key(channel: &str, key: &str) -> String {
+    format!("key:{}:{}", channel, key)
+}
+
+fn digest_key(channel: &str, text: &str, desp: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input_str(text);
+    hasher.input(&[0]);
+    hasher.input_str(desp);
+    format!("digest:{}:{}", channel, hasher.result_str())
+}
+
+impl DedupeInterface {
+    pub fn new() -> DedupeInterface {
+        DedupeInterface {
+            storage: super::storage::SingleKvStorage::new(&super::CONFIG.db_path, STORE),
+            last_clean: RwLock::new(0),
+        }
+    }
+
+    fn get_record(&self, key: &str) -> Option<Record> {
+        self.storage
+            .get_single(key)
+            .map(|record_string| serde_json::from_str(&record_string).unwrap())
+    }
+
+    fn put_record(&self, key: &str, id: &str) {
+        let record = Record {
+            id: id.to_string(),
+            time: chrono::Local::now().timestamp(),
+        };
+        let json_string = serde_json::to_string(&record).unwrap();
+        self.storage
+            .put_single(key, &rkv::Value::Json(&json_string));
+    }
+
+    // 查找重复提交的消息，返回原来的内容id
+    pub fn find(&self, channel: &str, key: Option<&str>, text: &str, desp: &str) -> Option<String> {
+        if let Some(key) = key {
+            if let Some(record) = self.get_record(&idempotency_key(channel, key)) {
+                // 内容过期后幂等键也失效，正在推送的由reserve处理
+                if !record.id.is_empty()
+                    && super::content::INTERFACE.get_content(&record.id).is_ok()
+                {
+                    return Some(record.id);
+                }
+            }
+        }
+        let window = super::CONFIG.dedupe_window;
+        if window > 0 {
+            if let Some(record) = self.get_record(&digest_key(channel, text, desp)) {
+                if !record.id.is_empty() && chrono::Local::now().timestamp() - record.time < window
+                {
+                    return Some(record.id);
+                }
+            }
+        }
+        None
+    }
+
+    // find没有找到重复时在推送前占用记录，检查和写入在同一个事务中
+    // 返回Err(Some(id))表示期间已经有相同的消息推送完成，Err(None)表示相同的消息正在推送
+    // since是调用find之前的时间，在这之后写入的记录才是并发请求留下的
+    pub fn reserve(
+        &self,
+        channel: &str,
+        key: Option<&str>,
+        text: &str,
+        desp: &str,
+        since: i64,
+    ) -> Result<(), Option<String>> {
+        let mut keys = Vec::new();
+        if let Some(key) = key {
+            keys.push(idempotency_key(channel, key));
+        }
+        if super::CONFIG.dedupe_window > 0 {
+            keys.push(digest_key(channel, text, desp));
+        }
+        let now = chrono::Local::now().timestamp();
+        let env = self.storage.env.read().unwrap();
+        let mut writer = env.write().unwrap();
+        for key in &keys {
+            if let Some(record_string) = self.storage.get_json(&writer, key) {
+                let record: Record = serde_json::from_str(&record_string).unwrap();
+                if record.id.is_empty() && now - record.time < RESERVE_TIMEOUT {
+                    return Err(None);
+                }
+                if !record.id.is_empty() && record.time >= since {
+                    return Err(Some(record.id));
+                }
+            }
+        }
+        let json_string = serde_json::to_string(&Record {
+            id: String::new(),
+            time: now,
+        })
+        .unwrap();
+        for key in &keys {
+            self.storage.put_json(&mut writer, key, &json_string);
+        }
+        writer.commit().unwrap();
+        Ok(())
+    }
+
+    // 推送完成后写入内容id
+    pub fn record(&self, channel: &str, key: Option<&str>, text: &str, desp: &str, id: &str) {
+        if let Some(key) = key {
+            self.put_record(&idempotency_key(channel, key), id);
+        }
+        if super::CONFIG.dedupe_window > 0 {
+            self.put_record(&digest_key(channel, text, desp), id);
+        }
+    }
+
+    // 清理失效的记录
+    pub fn clean(&self) {
+        let now = chrono::Local::now().timestamp();
+        {
+            let mut last_clean = self.last_clean.write().unwrap();
+            if now - *last_clean < CLEAN_INTERVAL {
+                return;
+            }
+            *last_clean = now;
+        }
+        let mut records = Vec::new();
+        {
+            let env = self.storage.env.read().unwrap();
+            let reader = env.read().unwrap();
+            let mut iter = self.storage.single.iter_start(&reader).unwrap();
+            while let Some(Ok((key, record))) = iter.next() {
+                if let Some(rkv::Value::Json(record)) = record {
+                    let key = std::str::from_utf8(key).unwrap().to_string();
+                    let record: Record = serde_json::from_str(record).unwrap();
+                    records.push((key, record));
+                }
+            }
+        }
+        for (key, record) in records {
+            let expired = if record.id.is_empty() {
+                now - record.time >= RESERVE_TIMEOUT
+            } else if key.starts_with("digest:") {
+                now - record.time >= super::CONFIG.dedupe_window
+            } else {
+                super::content::INTERFACE.get_content(&record.id).is_err()
+            };
+            if expired {
+                debug!("del dedupe:{}", key);
+                self.storage.del_single(&key);
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use super::user::DigestMode;
+use chrono::{Local, TimeZone, Timelike};
+
+// 等待合并推送的消息
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct Pending {
+    pub user: String,
+    pub channel: String,
+    // 第一条消息的时间
+    pub since: i64,
+    // 内容id
+    pub items: Vec<String>,
+}
+
+// 已经推送的摘要，用于展示摘要页
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct Digest {
+    pub id: String,
+    pub user: String,
+    pub channel: String,
+    pub items: Vec<String>,
+    pub time: i64,
+}
+
+const STORE: &str = "digest";
+const STORE_PENDING: &str = "digest_pending";
+
+lazy_static! {
+    pub static ref INTERFACE: DigestInterface = DigestInterface::new();
+}
+
+pub struct DigestInterface {
+    // id/digest
+    storage: super::storage::SingleKvStorage,
+    // 用户:频道/pending
+    storage_pending: super::storage::SingleKvStorage,
+}
+
+fn pending_key(user: &str, channel: &str) -> String {
+    format!("{}:{}", user, channel)
+}
+
+// 是否到了推送摘要的时间
+pub fn is_due(mode: DigestMode, since: i64, now: i64) -> bool {
+    let since = Local.timestamp(since, 0);
+    let hour_start = since.with_minute(0).unwrap().with_second(0).unwrap();
+    let due = match mode {
+        // 下一个整点
+        DigestMode::Hourly => hour_start + chrono::Duration::hours(1),
+        // 下一个每日推送时间
+        DigestMode::Daily => {
+            let today = since
+                .date()
+                .and_hms(super::CONFIG.digest_daily_hour % 24, 0, 0);
+            if today > since {
+                today
+            } else {
+                today + chrono::Duration::days(1)
+            }
+        }
+    };
+    now >= due.timestamp()
+}
+
+impl DigestInterface {
+    pub fn new() -> DigestInterface {
+        DigestInterface {
+            storage: super::storage::SingleKvStorage::new(&super::CONFIG.db_path, STORE),
+            storage_pending: super::storage::SingleKvStorage::new(
+                &super::CONFIG.db_path,
+                STORE_PENDING,
+            ),
+        }
+    }
+
+    pub fn add_pending(&self, user: &str, channel: &str, content: &str) {
+        self.storage_pending
+            .update_single(&pending_key(user, channel), |value| {
+                let mut pending = match value {
+                    Some(pending_string) => serde_json::from_str(pending_string).unwrap(),
+                    None => Pending {
+                        user: user.to_string(),
+                        channel: channel.to_string(),
+                        since: chrono::Local::now().timestamp(),
+                        items: Vec::new(),
+                    },
+                };
+                pending.items.push(content.to_string());
+                *value = Some(serde_json::to_string(&pending).unwrap());
+            });
+    }
+
+    pub fn get_all_pending(&self) -> Vec<Pending> {
+        let env = self.storage_pending.env.read().unwrap();
+        let reader = env.read().unwrap();
+        let mut iter = self.storage_pending.single.iter_start(&reader).unwrap();
+        let mut ret = Vec::new();
+        while let Some(Ok((_, pending))) = iter.next() {
+            if let Some(rkv::Value::Json(pending)) = pending {
+                ret.push(serde_json::from_str(pending).unwrap());
+            }
+        }
+        ret
+    }
+
+    pub fn del_pending(&self, user: &str, channel: &str) {
+        self.storage_pending.del_single(&pending_key(user, channel));
+    }
+
+    // 读取并删除，避免和add_pending交错时丢消息
+    pub fn take_pending(&self, user: &str, channel: &str) -> Option<Pending> {
+        self.storage_pending
+            .update_single(&pending_key(user, channel), |value| {
+                value
+                    .take()
+                    .map(|pending_string| serde_json::from_str(&pending_string).unwrap())
+            })
+    }
+
+    // 返回摘要id
+    pub fn add_digest(&self, user: &str, channel: &str, items: &[String]) -> String {
+        let id = uuid::Uuid::new_v4().to_simple().to_string();
+        let digest = Digest {
+            id: id.clone(),
+            user: user.to_string(),
+            channel: channel.to_string(),
+            items: items.to_vec(),
+            time: chrono::Local::now().timestamp(),
+        };
+        let json_string = serde_json::to_string(&digest).unwrap();
+        self.storage
+            .put_single(&id, &rkv::Value::Json(&json_string));
+        id
+    }
+
+    pub fn get_digest(&self, id: &str) -> Result<Digest, &str> {
+        match self.storage.get_single(id) {
+            Some(digest_string) => Ok(serde_json::from_str(&digest_string).unwrap()),
+            None => Err("没找到对应摘要"),
+        }
+    }
+
+    // 删除指定时间之前的摘要
+    pub fn clean_digests(&self, before: i64) {
+        let mut ids = Vec::new();
+        {
+            let env = self.storage.env.read().unwrap();
+            let reader = env.read().unwrap();
+            let mut iter = self.storage.single.iter_start(&reader).unwrap();
+            while let Some(Ok((_, digest))) = iter.next() {
+                if let Some(rkv::Value::Json(digest)) = digest {
+                    let digest: Digest = serde_json::from_str(digest).unwrap();
+                    if digest.time < before {
+                        ids.push(digest.id);
+                    }
+                }
+            }
+        }
+        for id in ids {
+            debug!("del digest:{}", id);
+            self.storage.del_single(&id);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// 转义html特殊字符
+pub fn escape(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&#39;"),
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+// 把markdown渲染成html
+pub fn render_markdown(text: &str) -> String {
+    let parser = pulldown_cmark::Parser::new(text);
+    let mut ret = String::new();
+    pulldown_cmark::html::push_html(&mut ret, parser);
+    ret
+}
+
+// 生成简单页面，body需要调用方自行转义
+pub fn gen_page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8"/>
+  <meta name="viewport" content="width=device-width, initial-scale=1"/>
+  <title>{}</title>
+</head>
+<body>
+{}
+</body>
+</html>"#,
+        escape(title),
+        body
+    )
+}
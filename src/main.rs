@@ -14,9 +14,17 @@ extern crate lazy_static;
 #[macro_use]
 extern crate clap;
 
+mod blob;
 mod channel;
 mod config;
 mod content;
+mod cron;
+mod dedupe;
+mod digest;
+mod html;
+mod multipart;
+mod ratelimit;
+mod schedule;
 mod storage;
 mod user;
 
@@ -25,7 +33,8 @@ mod xml;
 mod access_token;
 mod wx_interface;
 
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use chrono::TimeZone;
 use std::fs;
 use std::io::prelude::*;
 use std::sync::Mutex;
@@ -93,181 +102,2054 @@ fn wx_auth(query: web::Query<AuthEchoInfo>) -> impl Responder {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 struct SubInfo {
     sendkey: String,
     text: String,
     desp: String,
+    idempotency_key: Option<String>,
+    // RFC3339格式的发送时间
+    send_at: Option<String>,
+    // 延迟发送的秒数
+    delay: Option<i64>,
+    // 消息优先级，urgent不受免打扰时段限制
+    priority: Option<String>,
+    // 逗号分隔的标签
+    tags: Option<String>,
+    // 为json时返回带内容id的json，默认只返回success
+    format: Option<String>,
+}
+
+// 保存内容并通过模板推送给频道订阅者，返回内容id
+fn push_message(chn: &channel::Channel, content: content::Content) -> String {
+    let text = content.title.clone();
+    let desp = content.body.clone();
+    let urgent = content.urgent;
+    let tags = content.tags.clone();
+    // 先获取token，获取失败时不会留下没有推送的内容
+    debug!("new wx_interface");
+    let wx = wx_interface::WxInterface::new();
+    debug!("wx get token");
+    let at = wx.get_access_token();
+    debug!("wx at:{:?}", at);
+    // 添加content
+    let id = content::INTERFACE.add_content(content);
+    // 通过模板发送消息
+    let subers = channel::INTERFACE.get_subscribers(&chn.id).unwrap();
+    let timestamp = chrono::Local::now().timestamp();
+    let now = format_time(timestamp);
+    for user in subers {
+        let option = user.get_option(&chn.id);
+        // 静音期间不推送
+        if option.is_muted(timestamp) {
+            continue;
+        }
+        // 不符合订阅者标签和过滤条件的消息不推送
+        if !option.accepts_tags(&tags) || !option.accepts(&text, &desp) {
+            continue;
+        }
+        // 摘要模式和免打扰时段内的订阅者稍后合并推送，紧急消息不受免打扰限制
+        let quiet = !urgent
+            && user
+                .quiet
+                .as_ref()
+                .map_or(false, |quiet| quiet.contains(timestamp));
+        if quiet || option.digest.is_some() {
+            digest::INTERFACE.add_pending(&user.id, &chn.id, &id);
+            continue;
+        }
+        if !ratelimit::INTERFACE.charge_template(&chn.id) {
+            debug!("template budget exhausted, skip:{}", user.id);
+            continue;
+        }
+        wx.send_template(
+            &CONFIG.template_id,
+            &user.id,
+            &chn.name,
+            &text,
+            &now,
+            &desp,
+            &format!("{}/content/{}", CONFIG.host, id),
+        );
+    }
+    id
+}
+
+// 需要时返回内容id，用于之后修改或撤回
+fn sub_success(id: &str, with_id: bool) -> HttpResponse {
+    if !with_id {
+        return HttpResponse::Ok().body("success");
+    }
+    HttpResponse::Ok().json(json!({
+        "code": 0,
+        "message": "success",
+        "id": id,
+    }))
+}
+
+// 返回定时消息id，用于之后取消
+fn sub_scheduled(id: &str) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "code": 0,
+        "message": "scheduled",
+        "schedule_id": id,
+    }))
+}
+
+// 检查发送频率限制，超出时返回429
+fn check_rate_limit(chn: &channel::Channel, sendkey: &str) -> Option<HttpResponse> {
+    match ratelimit::INTERFACE.acquire(&chn.id, sendkey) {
+        Ok(_) => None,
+        Err(retry_after) => Some(
+            HttpResponse::TooManyRequests()
+                .header("Retry-After", retry_after.to_string())
+                .body("发送太频繁"),
+        ),
+    }
+}
+
+// 定时发送最多提前一年
+const SCHEDULE_MAX_AHEAD: i64 = 365 * 24 * 3600;
+
+// 计算定时发送的时间戳，没有指定时返回None
+fn parse_send_at(info: &SubInfo) -> Result<Option<i64>, &'static str> {
+    let now = chrono::Local::now().timestamp();
+    if let Some(send_at) = &info.send_at {
+        return match chrono::DateTime::parse_from_rfc3339(send_at) {
+            Ok(time) if time.timestamp() - now > SCHEDULE_MAX_AHEAD => Err("发送时间太远"),
+            Ok(time) => Ok(Some(time.timestamp())),
+            Err(_) => Err("发送时间格式不对"),
+        };
+    }
+    match info.delay {
+        Some(delay) if !(0..=SCHEDULE_MAX_AHEAD).contains(&delay) => Err("延迟时间不对"),
+        Some(delay) => now.checked_add(delay).map(Some).ok_or("延迟时间不对"),
+        None => Ok(None),
+    }
+}
+
+fn do_sub(info: SubInfo, files: Vec<(String, Vec<u8>)>) -> HttpResponse {
+    // 先清理过期数据
+    content::INTERFACE.clean_contents();
+    dedupe::INTERFACE.clean();
+    let send_at = match parse_send_at(&info) {
+        Ok(send_at) => send_at,
+        Err(err) => return HttpResponse::BadRequest().body(err),
+    };
+    let urgent = match info.priority.as_deref() {
+        None | Some("normal") => false,
+        Some("urgent") => true,
+        _ => return HttpResponse::BadRequest().body("优先级不对"),
+    };
+    let with_id = info.format.as_deref() == Some("json");
+    // 通过sendkey获取channel
+    let (ch, label) = match channel::INTERFACE.authorize_sendkey(&info.sendkey, "send") {
+        Ok(ret) => ret,
+        Err(err) => return HttpResponse::BadRequest().body(err),
+    };
+    let key = info.idempotency_key.as_deref();
+    let since = chrono::Local::now().timestamp();
+    if let Some(id) = dedupe::INTERFACE.find(&ch.id, key, &info.text, &info.desp) {
+        debug!("duplicate content:{}", id);
+        return sub_success(&id, with_id);
+    }
+    if let Some(key) = key {
+        if let Some(id) = schedule::INTERFACE.find_by_key(&ch.id, key) {
+            debug!("duplicate scheduled:{}", id);
+            return sub_scheduled(&id);
+        }
+    }
+    // 定时消息在发送时再检查频率限制
+    let now = chrono::Local::now().timestamp();
+    let scheduled = send_at.filter(|send_at| *send_at > now);
+    if scheduled.is_none() {
+        if let Some(resp) = check_rate_limit(&ch, &info.sendkey) {
+            return resp;
+        }
+        match dedupe::INTERFACE.reserve(&ch.id, key, &info.text, &info.desp, since) {
+            Ok(_) => (),
+            Err(Some(id)) => return sub_success(&id, with_id),
+            Err(None) => return HttpResponse::Conflict().body("相同的消息正在发送"),
+        }
+    }
+    let attachments: Vec<String> = files
+        .iter()
+        .map(|(name, data)| blob::INTERFACE.add_blob(&ch.id, name, data))
+        .collect();
+    let content = content::Content {
+        channel: ch.id.clone(),
+        title: info.text.clone(),
+        body: info.desp.clone(),
+        attachments,
+        sender: label,
+        urgent,
+        tags: info
+            .tags
+            .as_deref()
+            .map(content::parse_tags)
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    match scheduled {
+        Some(send_at) => {
+            let id = schedule::INTERFACE.add_scheduled(
+                &info.sendkey,
+                send_at,
+                content,
+                info.idempotency_key.clone(),
+            );
+            sub_scheduled(&id)
+        }
+        None => {
+            let id = push_message(&ch, content);
+            dedupe::INTERFACE.record(&ch.id, key, &info.text, &info.desp, &id);
+            sub_success(&id, with_id)
+        }
+    }
+}
+
+fn wx_sub(query: web::Query<SubInfo>) -> impl Responder {
+    debug!("get /sub");
+    debug!("query:{:?}", query);
+    do_sub(query.into_inner(), Vec::new())
+}
+
+// 带附件的推送，使用multipart/form-data提交
+fn wx_sub_upload(req: HttpRequest, body: web::Bytes) -> impl Responder {
+    debug!("post /sub");
+    let boundary = req
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .and_then(multipart::get_boundary);
+    let parts = match boundary {
+        Some(boundary) => match multipart::parse(&body, &boundary) {
+            Ok(parts) => parts,
+            Err(err) => return HttpResponse::BadRequest().body(err),
+        },
+        None => return HttpResponse::BadRequest().body("格式不对"),
+    };
+    let mut info = SubInfo::default();
+    let mut files = Vec::new();
+    for part in parts {
+        match part.filename {
+            Some(filename) => files.push((filename, part.data)),
+            None => {
+                let value = String::from_utf8_lossy(&part.data).to_string();
+                match part.name.as_str() {
+                    "sendkey" => info.sendkey = value,
+                    "text" => info.text = value,
+                    "desp" => info.desp = value,
+                    "idempotency_key" => info.idempotency_key = Some(value),
+                    "send_at" => info.send_at = Some(value),
+                    "priority" => info.priority = Some(value),
+                    "tags" => info.tags = Some(value),
+                    "format" => info.format = Some(value),
+                    "delay" => match value.parse::<i64>() {
+                        Ok(delay) => info.delay = Some(delay),
+                        Err(_) => return HttpResponse::BadRequest().body("延迟时间不对"),
+                    },
+                    _ => (),
+                }
+            }
+        }
+    }
+    if files.len() > CONFIG.attachment_max_count {
+        return HttpResponse::BadRequest().body("附件数量超过上限");
+    }
+    if files
+        .iter()
+        .any(|(_, data)| data.len() > CONFIG.attachment_max_size)
+    {
+        return HttpResponse::PayloadTooLarge().body("附件大小超过上限");
+    }
+    do_sub(info, files)
+}
+
+#[derive(Deserialize, Debug)]
+struct ScheduledInfo {
+    sendkey: String,
+}
+
+fn wx_scheduled(query: web::Query<ScheduledInfo>) -> impl Responder {
+    debug!("get /scheduled");
+    match channel::INTERFACE.authorize_sendkey(&query.sendkey, "send") {
+        Ok((chn, _)) => {
+            let list: Vec<serde_json::Value> = schedule::INTERFACE
+                .get_by_channel(&chn.id)
+                .into_iter()
+                .map(|scheduled| {
+                    json!({
+                        "id": scheduled.id,
+                        "send_at": chrono::Local.timestamp(scheduled.send_at, 0).to_rfc3339(),
+                        "text": scheduled.content.title,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({
+                "code": 0,
+                "message": "success",
+                "scheduled": list,
+            }))
+        }
+        Err(err) => HttpResponse::BadRequest().body(err),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CancelInfo {
+    sendkey: String,
+    id: String,
+}
+
+fn wx_cancel(query: web::Query<CancelInfo>) -> impl Responder {
+    debug!("get /cancel");
+    match channel::INTERFACE.authorize_sendkey(&query.sendkey, "send") {
+        Ok((chn, _)) => match schedule::INTERFACE.cancel(&chn.id, &query.id) {
+            Ok(_) => sub_scheduled(&query.id),
+            Err(err) => HttpResponse::BadRequest().body(err),
+        },
+        Err(err) => HttpResponse::BadRequest().body(err),
+    }
+}
+
+// 定时检查间隔，单位秒
+const SCHEDULER_INTERVAL: u64 = 10;
+// 后台任务出错后重试的间隔，单位秒
+const SCHEDULER_RETRY: i64 = 60;
+
+// 捕获后台任务中的panic，避免网络请求出错时定时线程退出
+fn run_job<T>(name: &str, job: impl FnOnce() -> T) -> Option<T> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)) {
+        Ok(ret) => Some(ret),
+        Err(_) => {
+            error!("scheduler job {} panicked", name);
+            None
+        }
+    }
+}
+
+// 发送到时间的定时消息
+fn run_scheduled() {
+    let now = chrono::Local::now().timestamp();
+    for mut scheduled in schedule::INTERFACE.get_due(now) {
+        let chn = match channel::INTERFACE.get_channel_by_id(&scheduled.channel) {
+            Ok(chn) => chn,
+            Err(err) => {
+                debug!("scheduled {}:{}", scheduled.id, err);
+                schedule::INTERFACE.remove(&scheduled.id);
+                continue;
+            }
+        };
+        if let Err(retry_after) = ratelimit::INTERFACE.acquire(&chn.id, &scheduled.sendkey) {
+            schedule::INTERFACE.postpone(&mut scheduled, now + retry_after as i64);
+            continue;
+        }
+        let id = match run_job(&scheduled.id, || {
+            push_message(&chn, scheduled.content.clone())
+        }) {
+            Some(id) => id,
+            None => {
+                schedule::INTERFACE.postpone(&mut scheduled, now + SCHEDULER_RETRY);
+                continue;
+            }
+        };
+        dedupe::INTERFACE.record(
+            &chn.id,
+            scheduled.idempotency_key.as_deref(),
+            &scheduled.content.title,
+            &scheduled.content.body,
+            &id,
+        );
+        schedule::INTERFACE.remove(&scheduled.id);
+    }
+}
+
+// 替换周期消息模板中的时间
+fn fill_template(template: &str, time: i64) -> String {
+    let time = chrono::Local.timestamp(time, 0);
+    template
+        .replace("{date}", &time.format("%Y-%m-%d").to_string())
+        .replace("{time}", &time.format("%H:%M").to_string())
+}
+
+// 发送到时间的周期消息
+fn run_recurring() {
+    let now = chrono::Local::now().timestamp();
+    for mut recurring in schedule::INTERFACE.get_due_recurring(now) {
+        let chn = match channel::INTERFACE.get_channel_by_id(&recurring.channel) {
+            Ok(chn) => chn,
+            Err(err) => {
+                debug!("recurring {}:{}", recurring.id, err);
+                let _ = schedule::INTERFACE.del_recurring(&recurring.channel, &recurring.id);
+                continue;
+            }
+        };
+        let run_at = recurring.next_run;
+        schedule::INTERFACE.advance_recurring(&mut recurring, now);
+        let sendkey = format!("cron:{}", recurring.id);
+        if ratelimit::INTERFACE.acquire(&chn.id, &sendkey).is_err() {
+            debug!("recurring {} rate limited", recurring.id);
+            continue;
+        }
+        let pushed = run_job(&recurring.id, || {
+            push_message(
+                &chn,
+                content::Content {
+                    channel: chn.id.clone(),
+                    title: fill_template(&recurring.text, run_at),
+                    body: fill_template(&recurring.desp, run_at),
+                    sender: "cron".to_string(),
+                    ..Default::default()
+                },
+            )
+        });
+        // 出错时稍后重试这一次，不用等到下个周期
+        if pushed.is_none() {
+            schedule::INTERFACE.postpone_recurring(&mut recurring, now + SCHEDULER_RETRY);
+        }
+    }
+}
+
+// 摘要推送时正文里列出的标题数量
+const DIGEST_TITLE_SIZE: usize = 5;
+
+// 合并推送摘要
+fn run_digest() {
+    let now = chrono::Local::now().timestamp();
+    for pending in digest::INTERFACE.get_all_pending() {
+        let user = match user::INTERFACE.get_user(&pending.user) {
+            Ok(user) => user,
+            Err(_) => {
+                digest::INTERFACE.del_pending(&pending.user, &pending.channel);
+                continue;
+            }
+        };
+        // 已经取消订阅的直接丢弃
+        if !user.subscribes.contains(&pending.channel) {
+            digest::INTERFACE.del_pending(&pending.user, &pending.channel);
+            continue;
+        }
+        // 关闭摘要模式后立即推送剩下的消息
+        let mode = user.get_option(&pending.channel).digest;
+        if let Some(mode) = mode {
+            if !digest::is_due(mode, pending.since, now) {
+                continue;
+            }
+        }
+        // 免打扰时段结束后再推送
+        if user
+            .quiet
+            .as_ref()
+            .map_or(false, |quiet| quiet.contains(now))
+        {
+            continue;
+        }
+        let chn = match channel::INTERFACE.get_channel_by_id(&pending.channel) {
+            Ok(chn) => chn,
+            Err(_) => {
+                digest::INTERFACE.del_pending(&pending.user, &pending.channel);
+                continue;
+            }
+        };
+        // 取出和删除在同一个事务中，避免丢掉刚加入的消息
+        let pending = match digest::INTERFACE.take_pending(&pending.user, &pending.channel) {
+            Some(pending) => pending,
+            None => continue,
+        };
+        let contents: Vec<(String, content::Content)> = pending
+            .items
+            .iter()
+            .filter_map(|id| {
+                content::INTERFACE
+                    .get_content(id)
+                    .ok()
+                    .map(|content| (id.clone(), content))
+            })
+            .filter(|(_, content)| !content.recalled)
+            .collect();
+        if contents.is_empty() {
+            continue;
+        }
+        // 放回去稍后再推送
+        let restore = || {
+            for (id, _) in &contents {
+                digest::INTERFACE.add_pending(&pending.user, &pending.channel, id);
+            }
+        };
+        // 额度用完时等额度恢复后再推送
+        if !ratelimit::INTERFACE.charge_template(&chn.id) {
+            restore();
+            continue;
+        }
+        // 免打扰期间只积压了一条消息时按原样推送
+        if mode.is_none() && contents.len() == 1 {
+            let (id, content) = &contents[0];
+            let sent = run_job(&pending.user, || {
+                wx_interface::INTERFACE.send_template(
+                    &CONFIG.template_id,
+                    &pending.user,
+                    &chn.name,
+                    &content.title,
+                    &format_time(content.time),
+                    &content.body,
+                    &format!("{}/content/{}", CONFIG.host, id),
+                )
+            });
+            if sent.is_none() {
+                restore();
+            }
+            continue;
+        }
+        let titles: Vec<&str> = contents
+            .iter()
+            .map(|(_, content)| content.title.as_str())
+            .collect();
+        let id = digest::INTERFACE.add_digest(&pending.user, &pending.channel, &pending.items);
+        let mut body = titles
+            .iter()
+            .take(DIGEST_TITLE_SIZE)
+            .cloned()
+            .collect::<Vec<&str>>()
+            .join("\n");
+        if titles.len() > DIGEST_TITLE_SIZE {
+            body.push_str("\n...");
+        }
+        let sent = run_job(&pending.user, || {
+            wx_interface::INTERFACE.send_template(
+                &CONFIG.template_id,
+                &pending.user,
+                &chn.name,
+                &format!("{}条新消息", titles.len()),
+                &format_time(now),
+                &body,
+                &format!("{}/digest/{}", CONFIG.host, id),
+            )
+        });
+        if sent.is_none() {
+            restore();
+        }
+    }
+    // 摘要和内容一起过期
+    if CONFIG.content_expire > 0 {
+        digest::INTERFACE.clean_digests(now - i64::from(CONFIG.content_expire) * 24 * 3600);
+    }
+}
+
+fn run_scheduler() {
+    info!("scheduler started.");
+    loop {
+        run_job("scheduled", run_scheduled);
+        run_job("recurring", run_recurring);
+        run_job("digest", run_digest);
+        run_job("qrcode", || channel::INTERFACE.refresh_qrcodes());
+        std::thread::sleep(std::time::Duration::from_secs(SCHEDULER_INTERVAL));
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct EditInfo {
+    sendkey: String,
+    id: String,
+    desp: String,
+}
+
+// 确认sendkey有对应权限并且内容属于sendkey对应的频道
+fn check_content_owner(sendkey: &str, scope: &str, id: &str) -> Result<(), HttpResponse> {
+    let chn = match channel::INTERFACE.authorize_sendkey(sendkey, scope) {
+        Ok((chn, _)) => chn,
+        Err(err) => return Err(HttpResponse::BadRequest().body(err)),
+    };
+    match content::INTERFACE.get_content(id) {
+        Ok(content) => {
+            if content.channel == chn.id {
+                Ok(())
+            } else {
+                Err(HttpResponse::Forbidden().body("内容不属于该频道"))
+            }
+        }
+        Err(err) => Err(HttpResponse::NotFound().body(err)),
+    }
+}
+
+fn wx_edit(query: web::Query<EditInfo>) -> impl Responder {
+    debug!("get /edit");
+    debug!("query:{:?}", query);
+    if let Err(resp) = check_content_owner(&query.sendkey, "edit", &query.id) {
+        return resp;
+    }
+    match content::INTERFACE.update_content(&query.id, &query.desp) {
+        Ok(_) => sub_success(&query.id, true),
+        Err(err) => HttpResponse::BadRequest().body(err),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct RecallInfo {
+    sendkey: String,
+    id: String,
+}
+
+fn wx_recall(query: web::Query<RecallInfo>) -> impl Responder {
+    debug!("get /recall");
+    debug!("query:{:?}", query);
+    if let Err(resp) = check_content_owner(&query.sendkey, "recall", &query.id) {
+        return resp;
+    }
+    match content::INTERFACE.recall_content(&query.id) {
+        Ok(_) => sub_success(&query.id, true),
+        Err(err) => HttpResponse::BadRequest().body(err),
+    }
+}
+
+// 检查管理接口凭证，没有配置凭证时不开放管理接口
+fn check_admin_token(token: &str) -> bool {
+    !CONFIG.admin_token.is_empty() && CONFIG.admin_token == token
+}
+
+#[derive(Deserialize, Debug)]
+struct AdminResetSendkeyInfo {
+    token: String,
+    channel: String,
+    grace: Option<i64>,
+}
+
+fn admin_reset_sendkey(query: web::Query<AdminResetSendkeyInfo>) -> impl Responder {
+    debug!("get /admin/reset_sendkey");
+    if !check_admin_token(&query.token) {
+        return HttpResponse::Forbidden().finish();
+    }
+    match channel::INTERFACE.admin_reset_sendkey(&query.channel, query.grace.unwrap_or(0)) {
+        Ok(sendkey) => HttpResponse::Ok().json(json!({
+            "code": 0,
+            "message": "success",
+            "sendkey": sendkey,
+        })),
+        Err(err) => HttpResponse::BadRequest().body(err),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct AdminCronInfo {
+    token: String,
+    channel: String,
+    id: Option<String>,
+    cron: Option<String>,
+    text: Option<String>,
+    desp: Option<String>,
+}
+
+fn admin_cron_list(query: web::Query<AdminCronInfo>) -> impl Responder {
+    debug!("get /admin/cron/list");
+    if !check_admin_token(&query.token) {
+        return HttpResponse::Forbidden().finish();
+    }
+    let list: Vec<serde_json::Value> = schedule::INTERFACE
+        .get_recurring_by_channel(&query.channel)
+        .into_iter()
+        .map(|recurring| {
+            json!({
+                "id": recurring.id,
+                "cron": recurring.cron,
+                "text": recurring.text,
+                "desp": recurring.desp,
+                "next_run": chrono::Local.timestamp(recurring.next_run, 0).to_rfc3339(),
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({
+        "code": 0,
+        "message": "success",
+        "recurring": list,
+    }))
+}
+
+fn admin_cron_add(query: web::Query<AdminCronInfo>) -> impl Responder {
+    debug!("get /admin/cron/add");
+    if !check_admin_token(&query.token) {
+        return HttpResponse::Forbidden().finish();
+    }
+    if let Err(err) = channel::INTERFACE.get_channel_by_id(&query.channel) {
+        return HttpResponse::BadRequest().body(err);
+    }
+    let (cron, text) = match (&query.cron, &query.text) {
+        (Some(cron), Some(text)) => (cron, text),
+        _ => return HttpResponse::BadRequest().body("格式不对"),
+    };
+    let desp = query.desp.clone().unwrap_or_default();
+    match schedule::INTERFACE.add_recurring(&query.channel, cron, text, &desp) {
+        Ok(id) => HttpResponse::Ok().json(json!({
+            "code": 0,
+            "message": "success",
+            "id": id,
+        })),
+        Err(err) => HttpResponse::BadRequest().body(err),
+    }
+}
+
+fn admin_cron_del(query: web::Query<AdminCronInfo>) -> impl Responder {
+    debug!("get /admin/cron/del");
+    if !check_admin_token(&query.token) {
+        return HttpResponse::Forbidden().finish();
+    }
+    let id = match &query.id {
+        Some(id) => id,
+        None => return HttpResponse::BadRequest().body("格式不对"),
+    };
+    match schedule::INTERFACE.del_recurring(&query.channel, id) {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "code": 0,
+            "message": "success",
+            "id": id,
+        })),
+        Err(err) => HttpResponse::BadRequest().body(err),
+    }
+}
+
+// 内容正文加上附件链接，附件以markdown形式附在末尾
+fn content_markdown(content: &content::Content) -> String {
+    if content.recalled {
+        return "消息已撤回".to_string();
+    }
+    let mut body = content.body.clone();
+    if content.edited {
+        body.push_str("\n\n*（已编辑）*");
+    }
+    for id in &content.attachments {
+        if let Ok(blob) = blob::INTERFACE.get_blob(id) {
+            let url = format!("{}/blob/{}", CONFIG.host, blob.id);
+            if blob.mime.starts_with("image/") {
+                body.push_str(&format!("\n\n![{}]({})", blob.name, url));
+            } else {
+                body.push_str(&format!("\n\n[{}]({}) ({}字节)", blob.name, url, blob.size));
+            }
+        }
+    }
+    body
+}
+
+fn show_blob(path: web::Path<String>, query: web::Query<OAuthInfo>) -> impl Responder {
+    debug!("get /blob/{}", path);
+    match blob::INTERFACE.read_blob(&path) {
+        Ok((blob, data)) => {
+            if !blob.channel.is_empty() {
+                match channel::INTERFACE.get_channel_by_id(&blob.channel) {
+                    Ok(chn) => {
+                        if chn.private {
+                            let url = format!("{}/blob/{}", CONFIG.host, path);
+                            if let Some(resp) = oauth_guard(&chn, &url, &query.code) {
+                                return resp;
+                            }
+                        }
+                    }
+                    Err(_) => return HttpResponse::NotFound().finish(),
+                }
+            }
+            let mut resp = HttpResponse::Ok();
+            resp.content_type(blob.mime.as_str())
+                .header("X-Content-Type-Options", "nosniff");
+            // 图片直接展示，其它文件作为下载
+            if !blob.mime.starts_with("image/") {
+                let name: String =
+                    url::form_urlencoded::byte_serialize(blob.name.as_bytes()).collect();
+                resp.header(
+                    "Content-Disposition",
+                    format!("attachment; filename*=UTF-8''{}", name.replace("+", "%20")),
+                );
+            }
+            resp.body(data)
+        }
+        Err(err) => {
+            debug!("get blob:{}", err);
+            HttpResponse::NotFound().finish()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct OAuthInfo {
+    code: Option<String>,
+}
+
+// 通过网页授权确认访问者是频道订阅者
+// 返回None表示可以访问，否则返回需要直接响应的内容
+fn oauth_guard(chn: &channel::Channel, url: &str, code: &Option<String>) -> Option<HttpResponse> {
+    match code {
+        Some(code) => match wx_interface::INTERFACE.get_oauth_openid(code) {
+            Ok(openid) => {
+                debug!("oauth openid:{}", openid);
+                if chn.role_of(&openid).is_some() {
+                    None
+                } else {
+                    Some(HttpResponse::Forbidden().finish())
+                }
+            }
+            Err(err) => {
+                debug!("oauth:{}", err);
+                Some(HttpResponse::Forbidden().finish())
+            }
+        },
+        None => Some(
+            HttpResponse::Found()
+                .header("Location", wx_interface::INTERFACE.get_oauth_url(url))
+                .finish(),
+        ),
+    }
+}
+
+fn show_content(path: web::Path<String>, query: web::Query<OAuthInfo>) -> impl Responder {
+    debug!("get /content/{}", path);
+    // 获取content
+    match content::INTERFACE.get_content(&path.to_string()) {
+        Ok(content) => {
+            debug!("get content:{:?}", content);
+            if !content.channel.is_empty() {
+                match channel::INTERFACE.get_channel_by_id(&content.channel) {
+                    Ok(chn) => {
+                        if chn.private {
+                            let url = format!("{}/content/{}", CONFIG.host, path);
+                            if let Some(resp) = oauth_guard(&chn, &url, &query.code) {
+                                return resp;
+                            }
+                        }
+                    }
+                    Err(_) => return HttpResponse::NotFound().finish(),
+                }
+            }
+            // 替换模板
+            let output = DETAIL_TEMPLATE.replace("{::}", &content_markdown(&content));
+            HttpResponse::Ok().body(output)
+        }
+        Err(err) => {
+            debug!("get content:{}", err);
+            HttpResponse::NotFound().finish()
+        }
+    }
+}
+
+fn format_time(timestamp: i64) -> String {
+    chrono::Local
+        .timestamp(timestamp, 0)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+const HISTORY_PAGE_SIZE: usize = 20;
+const HISTORY_REPLY_SIZE: usize = 5;
+
+#[derive(Deserialize, Debug)]
+struct HistoryInfo {
+    page: Option<usize>,
+    code: Option<String>,
+}
+
+fn show_history(path: web::Path<String>, query: web::Query<HistoryInfo>) -> impl Responder {
+    debug!("get /channel/{}/history", path);
+    let chn = match channel::INTERFACE.get_channel_by_id(&path) {
+        Ok(chn) => chn,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let url = format!("{}/channel/{}/history?page={}", CONFIG.host, chn.id, page);
+    if let Some(resp) = oauth_guard(&chn, &url, &query.code) {
+        return resp;
+    }
+    let (total, contents) = content::INTERFACE.get_channel_contents(
+        &chn.id,
+        (page - 1).saturating_mul(HISTORY_PAGE_SIZE),
+        HISTORY_PAGE_SIZE,
+    );
+    let mut body = format!("<h1>{}</h1>\n<ul>\n", html::escape(&chn.name));
+    for (id, content) in contents {
+        body.push_str(&format!(
+            "<li><a href=\"{}/content/{}\">{}</a> {} {}</li>\n",
+            CONFIG.host,
+            id,
+            html::escape(content.display_title()),
+            format_time(content.time),
+            html::escape(&content.sender)
+        ));
+    }
+    body.push_str("</ul>\n<p>");
+    if page > 1 {
+        body.push_str(&format!("<a href=\"?page={}\">上一页</a> ", page - 1));
+    }
+    if page.saturating_mul(HISTORY_PAGE_SIZE) < total {
+        body.push_str(&format!("<a href=\"?page={}\">下一页</a>", page + 1));
+    }
+    body.push_str("</p>");
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html::gen_page(&format!("{} 历史消息", chn.name), &body))
+}
+
+#[derive(Deserialize, Debug)]
+struct DirectoryInfo {
+    q: Option<String>,
+}
+
+// 频道目录页
+fn show_directory(query: web::Query<DirectoryInfo>) -> impl Responder {
+    debug!("get /channels");
+    let keyword = query.q.clone().unwrap_or_default();
+    let mut body = format!(
+        r#"<h1>频道目录</h1>
+<form action="{}/channels"><input name="q" value="{}"/> <button type="submit">搜索</button></form>
+<ul>
+"#,
+        CONFIG.host,
+        html::escape(&keyword)
+    );
+    for chn in channel::INTERFACE.search(keyword.trim()) {
+        let qrcode = channel::INTERFACE.subscribe_qrcode(&chn.id);
+        body.push_str(&format!(
+            "<li><h3>{}</h3>\n<p>{}</p>\n<p>订阅人数:{} 订阅方式:{}</p>\n",
+            html::escape(&chn.name),
+            html::escape(&chn.description),
+            chn.subscribers.len(),
+            chn.visibility.describe()
+        ));
+        if !qrcode.is_empty() {
+            body.push_str(&format!(
+                "<img src=\"{}\" width=\"160\" alt=\"订阅二维码\"/>\n",
+                html::escape(&qrcode)
+            ));
+        }
+        body.push_str("</li>\n");
+    }
+    body.push_str("</ul>");
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html::gen_page("频道目录", &body))
+}
+
+fn show_digest(path: web::Path<String>, query: web::Query<OAuthInfo>) -> impl Responder {
+    debug!("get /digest/{}", path);
+    let digest = match digest::INTERFACE.get_digest(&path) {
+        Ok(digest) => digest,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    let name = match channel::INTERFACE.get_channel_by_id(&digest.channel) {
+        Ok(chn) => {
+            if chn.private {
+                let url = format!("{}/digest/{}", CONFIG.host, path);
+                if let Some(resp) = oauth_guard(&chn, &url, &query.code) {
+                    return resp;
+                }
+            }
+            chn.name
+        }
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    let mut body = format!("<h1>{}</h1>\n<ul>\n", html::escape(&name));
+    for id in &digest.items {
+        if let Ok(content) = content::INTERFACE.get_content(id) {
+            body.push_str(&format!(
+                "<li><a href=\"{}/content/{}\">{}</a> {}</li>\n",
+                CONFIG.host,
+                id,
+                html::escape(content.display_title()),
+                format_time(content.time)
+            ));
+        }
+    }
+    body.push_str("</ul>");
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html::gen_page(&format!("{} 消息摘要", name), &body))
+}
+
+const FEED_SIZE: usize = 20;
+
+#[derive(Deserialize, Debug)]
+struct FeedInfo {
+    token: String,
+}
+
+fn show_feed(path: web::Path<String>, query: web::Query<FeedInfo>) -> impl Responder {
+    debug!("get /channel/{}/feed.atom", path);
+    let chn = match channel::INTERFACE.get_channel_by_id(&path) {
+        Ok(chn) => chn,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    if chn.feed_token.is_empty() || chn.feed_token != query.token {
+        return HttpResponse::Forbidden().finish();
+    }
+    let (_, contents) = content::INTERFACE.get_channel_contents(&chn.id, 0, FEED_SIZE);
+    let mut updated = 0;
+    let mut entries = Vec::new();
+    for (id, content) in contents {
+        updated = updated.max(content.updated_at());
+        entries.push(xml::FeedEntry {
+            id: format!("{}/content/{}", CONFIG.host, id),
+            title: content.display_title().to_string(),
+            link: format!("{}/content/{}", CONFIG.host, id),
+            updated: chrono::Local
+                .timestamp(content.updated_at(), 0)
+                .to_rfc3339(),
+            content: html::render_markdown(&content_markdown(&content)),
+        });
+    }
+    let feed = xml::gen_atom_feed(
+        &format!("{}/channel/{}", CONFIG.host, chn.id),
+        &chn.name,
+        &format!("{}/channel/{}/history", CONFIG.host, chn.id),
+        &chrono::Local.timestamp(updated, 0).to_rfc3339(),
+        &entries,
+    );
+    HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(feed)
+}
+
+#[derive(Deserialize, Debug)]
+struct AuthInfo {
+    signature: String,
+    timestamp: String,
+    nonce: String,
+}
+
+// 命令中的频道参数可以是频道id或者handle，统一转换为频道id
+fn channel_arg(v: &[&str], i: usize) -> String {
+    v.get(i)
+        .map(|id| channel::INTERFACE.resolve(id))
+        .unwrap_or_default()
+}
+
+fn show_channel(msg: xml::UniversMessage) -> String {
+    let owner = msg.from.clone().unwrap();
+    let mut channel_info: String = String::new();
+    let channels = channel::INTERFACE.get_channel_by_manager(&owner).unwrap();
+    if channels.is_empty() {
+        channel_info.push_str("没有创建的频道");
+    } else {
+        for channel in channels {
+            let mut subscribers = String::new();
+            match channel::INTERFACE.get_subscribers(&channel.id) {
+                Ok(users) => {
+                    for user in users {
+                        subscribers.push_str(&format!("{}({}) ", &user.name, &user.id));
+                    }
+                }
+                _err => (),
+            }
+            channel_info.push_str(&format!(
+                r#"频道名:{}
+频道ID:{}
+Handle:{}
+SendKey:{}
+角色:{}
+订阅方式:{}
+订阅者:{}
+"#,
+                &channel.name,
+                &channel.id,
+                &channel.handle,
+                &channel.sendkey,
+                channel.role_of(&owner).map_or("", |role| role.describe()),
+                channel.visibility.describe(),
+                &subscribers
+            ));
+            if let Some(ref pending_owner) = channel.pending_owner {
+                channel_info.push_str(&format!("等待确认转让给:{}\n", pending_owner));
+            }
+            if !channel.admins.is_empty() {
+                channel_info.push_str(&format!("管理员:{}\n", channel.admins.join(" ")));
+            }
+            if !channel.banned.is_empty() {
+                channel_info.push_str(&format!("禁止订阅:{}\n", channel.banned.join(" ")));
+            }
+            debug!("{}", &channel_info);
+        }
+    }
+    xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), &channel_info)
+}
+
+fn show_subscribe(msg: xml::UniversMessage) -> String {
+    let owner = msg.from.clone().unwrap();
+    let user = user::INTERFACE.get_user(&owner).unwrap();
+    let mut channel_infos: String = String::new();
+    if user.subscribes.is_empty() {
+        channel_infos.push_str("没有订阅的频道");
+    } else {
+        for channel in &user.subscribes {
+            match channel::INTERFACE.get_channel_by_id(channel) {
+                Ok(chn) => {
+                    channel_infos.push_str(&format!(
+                        r#"频道名:{}
+频道ID:{}
+"#,
+                        &chn.name, &chn.id
+                    ));
+                    if !chn.description.is_empty() {
+                        channel_infos.push_str(&format!("简介:{}\n", &chn.description));
+                    }
+                    let option = user.get_option(&chn.id);
+                    match option.digest {
+                        Some(user::DigestMode::Hourly) => channel_infos.push_str("摘要:每小时\n"),
+                        Some(user::DigestMode::Daily) => channel_infos.push_str("摘要:每天\n"),
+                        None => (),
+                    }
+                    if !option.tags.is_empty() {
+                        channel_infos.push_str(&format!("标签:{}\n", option.tags.join(",")));
+                    }
+                    for filter in &option.filters {
+                        channel_infos.push_str(&format!("过滤:{}\n", filter.describe()));
+                    }
+                    match option.muted_until {
+                        Some(0) => channel_infos.push_str("已静音\n"),
+                        Some(until) if until > chrono::Local::now().timestamp() => {
+                            channel_infos.push_str(&format!("静音至:{}\n", format_time(until)))
+                        }
+                        _ => (),
+                    }
+                }
+                _err => (),
+            }
+            debug!("{}", &channel_infos);
+        }
+    }
+    xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), &channel_infos)
+}
+
+fn del_channel(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().splitn(3, ' ').collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 3 {
+        xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对")
+    } else {
+        let owner = msg.from.clone().unwrap();
+        match channel::INTERFACE.delete_channel(&id, &owner) {
+            Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+            Err(err) => xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), err),
+        }
+    }
+}
+fn add_channel(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().splitn(3, ' ').collect();
+    if v.len() != 3 {
+        xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对")
+    } else {
+        let owner = msg.from.clone().unwrap();
+        match channel::INTERFACE.add_channel(v[2], &owner) {
+            Ok(cid) => {
+                xml::gen_message_reply(&owner, &msg.to.unwrap(), &format!("操作成功,id:{}", &cid))
+            }
+            Err(err) => xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), err),
+        }
+    }
+}
+
+fn set_private(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().splitn(4, ' ').collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 4 || (v[3] != "on" && v[3] != "off") {
+        xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对")
+    } else {
+        let owner = msg.from.clone().unwrap();
+        match channel::INTERFACE.set_private(&id, &owner, v[3] == "on") {
+            Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+            Err(err) => xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), err),
+        }
+    }
+}
+
+// rename channel 频道id 名称，describe channel 频道id [简介]
+fn edit_channel(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    // 名称和简介中可能有空格
+    let v: Vec<&str> = content.as_str().splitn(4, ' ').collect();
+    let id = channel_arg(&v, 2);
+    if v.len() < 3 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let text = v.get(3).map_or("", |text| text.trim());
+    let owner = msg.from.clone().unwrap();
+    let ret = match v[0] {
+        "rename" => channel::INTERFACE.rename(&id, &owner, text),
+        _ => channel::INTERFACE.describe(&id, &owner, text),
+    };
+    match ret {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn set_handle(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 3 && v.len() != 4 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    match channel::INTERFACE.set_handle(&id, &owner, v.get(3).cloned().unwrap_or("")) {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+// 订阅成功时的回复
+fn subscribed_reply(chn: &channel::Channel) -> String {
+    if chn.description.is_empty() {
+        format!("已订阅频道:{}", chn.name)
+    } else {
+        format!("已订阅频道:{}\n{}", chn.name, chn.description)
+    }
+}
+
+fn set_listed(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 4 || (v[3] != "on" && v[3] != "off") {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    match channel::INTERFACE.set_listed(&id, &owner, v[3] == "on") {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+// 搜索结果最多回复的频道数量
+const SEARCH_REPLY_SIZE: usize = 10;
+
+fn search_channel(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().splitn(2, ' ').collect();
+    let keyword = v.get(1).map_or("", |keyword| keyword.trim());
+    let channels = channel::INTERFACE.search(keyword);
+    let mut reply = String::new();
+    if channels.is_empty() {
+        reply.push_str("没有找到频道");
+    }
+    for chn in channels.iter().take(SEARCH_REPLY_SIZE) {
+        let id = if chn.handle.is_empty() {
+            &chn.id
+        } else {
+            &chn.handle
+        };
+        reply.push_str(&format!(
+            r#"频道名:{}
+订阅人数:{}
+<a href="weixin://bizmsgmenu?msgmenucontent=subscribe%20{}&msgmenuid=1">订阅</a>
+"#,
+            &chn.name,
+            chn.subscribers.len(),
+            id
+        ));
+        if !chn.description.is_empty() {
+            reply.push_str(&format!("简介:{}\n", &chn.description));
+        }
+    }
+    if channels.len() > SEARCH_REPLY_SIZE {
+        reply.push_str(&format!(
+            "共{}个频道，查看全部:{}/channels",
+            channels.len(),
+            CONFIG.host
+        ));
+    }
+    xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), &reply)
+}
+
+fn set_visibility(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    let visibility = if v.len() == 4 {
+        channel::Visibility::parse(v[3])
+    } else {
+        None
+    };
+    match visibility {
+        Some(visibility) => {
+            let owner = msg.from.clone().unwrap();
+            match channel::INTERFACE.set_visibility(&id, &owner, visibility) {
+                Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+                Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+            }
+        }
+        None => xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对"),
+    }
+}
+
+fn show_requests(msg: xml::UniversMessage) -> String {
+    let owner = msg.from.clone().unwrap();
+    let requests = channel::INTERFACE.get_requests(&owner);
+    let mut info = String::new();
+    if requests.is_empty() {
+        info.push_str("没有待审核的订阅申请");
+    }
+    for request in requests {
+        let name = match user::INTERFACE.get_user(&request.user) {
+            Ok(user) => user.name,
+            Err(_) => request.user.clone(),
+        };
+        info.push_str(&format!(
+            r#"申请ID:{}
+频道ID:{}
+申请人:{}({})
+时间:{}
+"#,
+            &request.id,
+            &request.channel,
+            &name,
+            &request.user,
+            format_time(request.time)
+        ));
+    }
+    xml::gen_message_reply(&owner, &msg.to.unwrap(), &info)
+}
+
+fn review_request(msg: xml::UniversMessage, approve: bool) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let owner = msg.from.clone().unwrap();
+    if v.len() != 2 {
+        return xml::gen_message_reply(&owner, &msg.to.unwrap(), "格式不对");
+    }
+    match channel::INTERFACE.review_request(v[1], &owner, approve) {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn describe_invite(invite: &channel::Invite) -> String {
+    let uses = if invite.max_uses == 0 {
+        format!("{}/不限", invite.uses)
+    } else {
+        format!("{}/{}", invite.uses, invite.max_uses)
+    };
+    let expires = if invite.expires == 0 {
+        "不过期".to_string()
+    } else {
+        format_time(invite.expires)
+    };
+    let mut info = format!(
+        r#"邀请码:{}
+使用次数:{}
+过期时间:{}
+"#,
+        &invite.code, uses, expires
+    );
+    let qrcode = channel::INTERFACE.invite_qrcode(&invite.code);
+    if !qrcode.is_empty() {
+        info.push_str(&format!("<a href=\"{}\">二维码</a>\n", qrcode));
+    }
+    info
+}
+
+fn add_invite(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() < 3 || v.len() > 5 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let max_uses = v.get(3).map_or(Ok(0), |uses| uses.parse::<u32>());
+    let expire_days = v.get(4).map_or(Ok(0), |days| days.parse::<i64>());
+    let (max_uses, expire_days) = match (max_uses, expire_days) {
+        (Ok(max_uses), Ok(expire_days)) => (max_uses, expire_days),
+        _ => return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对"),
+    };
+    let owner = msg.from.clone().unwrap();
+    match channel::INTERFACE.add_invite(&id, &owner, max_uses, expire_days) {
+        Ok(invite) => xml::gen_message_reply(
+            &owner,
+            &msg.to.unwrap(),
+            &format!(
+                "操作成功,发送 join {} 即可订阅，二维码稍后可以通过 show invites 查看\n{}",
+                &invite.code,
+                describe_invite(&invite)
+            ),
+        ),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn show_invites(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 3 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    match channel::INTERFACE.get_invites(&id, &owner) {
+        Ok(invites) => {
+            let info = if invites.is_empty() {
+                "没有有效的邀请码".to_string()
+            } else {
+                invites
+                    .iter()
+                    .map(describe_invite)
+                    .collect::<Vec<String>>()
+                    .join("")
+            };
+            xml::gen_message_reply(&owner, &msg.to.unwrap(), &info)
+        }
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
 }
 
-fn wx_sub(query: web::Query<SubInfo>) -> impl Responder {
-    debug!("get /sub");
-    debug!("query:{:?}", query);
-    // 先清理过期数据
-    content::INTERFACE.clean_contents();
-    // 通过sendkey获取channel
-    let ch = channel::INTERFACE.get_channel_by_sendkey(&query.sendkey);
-    match ch {
-        Ok(_ch) => {
-            // 添加content
-            let id = content::INTERFACE.add_content(&query.desp);
-            // 通过模板发送消息
-            let subers = channel::INTERFACE.get_subscribers(&_ch.id).unwrap();
-            let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-            debug!("new wx_interface");
-            let wx = wx_interface::WxInterface::new();
-            debug!("wx get token");
-            let at = wx.get_access_token();
-            debug!("wx at:{:?}", at);
-            for user in subers {
-                wx.send_template(
-                    &CONFIG.template_id,
-                    &user.id,
-                    &_ch.name,
-                    &query.text,
-                    &now,
-                    &query.desp,
-                    &format!("{}/content/{}", CONFIG.host, id),
-                );
-            }
+fn revoke_invite(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 4 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    match channel::INTERFACE.revoke_invite(&id, &owner, v[3]) {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
 
-            HttpResponse::Ok().body("success")
-        }
-        Err(err) => HttpResponse::BadRequest().body(err),
+// 使用邀请码订阅，返回回复的文字
+fn redeem_invite(code: &str, user: &str) -> String {
+    match channel::INTERFACE.redeem_invite(code, user) {
+        Ok(chn) => subscribed_reply(&chn),
+        Err(err) => err.to_string(),
     }
 }
 
-fn show_content(path: web::Path<String>) -> impl Responder {
-    debug!("get /content/{}", path);
-    // 获取content
-    match content::INTERFACE.get_content(&path.to_string()) {
-        Ok(body) => {
-            debug!("get content:{}", body);
-            // 替换模板
-            let output = DETAIL_TEMPLATE.replace("{::}", &body);
-            HttpResponse::Ok().body(output)
-        }
-        Err(err) => {
-            debug!("get content:{}", err);
-            HttpResponse::NotFound().finish()
-        }
+// 处理带参数二维码的场景值，返回回复的文字
+fn handle_scene(scene: &str, user: &str) -> Option<String> {
+    if let Some(code) = scene.strip_prefix(channel::INVITE_SCENE_PREFIX) {
+        return Some(redeem_invite(code, user));
+    }
+    if let Some(id) = scene.strip_prefix(channel::SUBSCRIBE_SCENE_PREFIX) {
+        let reply = match channel::INTERFACE.subscribe(id, user, &[]) {
+            Ok(true) => match channel::INTERFACE.get_channel_by_id(id) {
+                Ok(chn) => subscribed_reply(&chn),
+                Err(err) => err.to_string(),
+            },
+            Ok(false) => "已提交订阅申请，请等待频道创建者审核".to_string(),
+            Err(err) => err.to_string(),
+        };
+        return Some(reply);
     }
+    None
 }
 
-#[derive(Deserialize, Debug)]
-struct AuthInfo {
-    signature: String,
-    timestamp: String,
-    nonce: String,
+fn join_channel(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    if v.len() != 2 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    xml::gen_message_reply(&owner, &msg.to.unwrap(), &redeem_invite(v[1], &owner))
 }
 
-fn show_channel(msg: xml::UniversMessage) -> String {
+// grant admin、revoke admin 频道id 用户id
+fn set_admin(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 4 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
     let owner = msg.from.clone().unwrap();
-    let mut channel_info: String = String::new();
-    let channels = channel::INTERFACE.get_channel_by_owner(&owner).unwrap();
-    if channels.is_empty() {
-        channel_info.push_str("没有创建的频道");
+    match channel::INTERFACE.set_admin(&id, &owner, v[3], v[0] == "grant") {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+// transfer 频道id 用户id，用户id为cancel时取消转让
+fn transfer_channel(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 1);
+    if v.len() != 3 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    let ret = if v[2] == "cancel" {
+        channel::INTERFACE
+            .cancel_transfer(&id, &owner)
+            .map(|_| "操作成功")
     } else {
-        for channel in channels {
-            let mut subscribers = String::new();
-            match channel::INTERFACE.get_subscribers(&channel.id) {
-                Ok(users) => {
-                    for user in users {
-                        subscribers.push_str(&format!("{}({}) ", &user.name, &user.id));
-                    }
-                }
-                _err => (),
+        channel::INTERFACE
+            .transfer(&id, &owner, v[2])
+            .map(|_| "已通知对方，对方确认后转让生效")
+    };
+    match ret {
+        Ok(reply) => xml::gen_message_reply(&owner, &msg.to.unwrap(), reply),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+// accept transfer、decline transfer 频道id
+fn confirm_transfer(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 3 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let user = msg.from.clone().unwrap();
+    match channel::INTERFACE.confirm_transfer(&id, &user, v[0] == "accept") {
+        Ok(_) => xml::gen_message_reply(&user, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&user, &msg.to.unwrap(), err),
+    }
+}
+
+// kick、ban、unban 频道id 用户id
+fn manage_subscriber(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 1);
+    if v.len() != 3 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    let ret = match v[0] {
+        "kick" => channel::INTERFACE.kick(&id, &owner, v[2]),
+        "ban" => channel::INTERFACE.ban(&id, &owner, v[2]),
+        "unban" => channel::INTERFACE.unban(&id, &owner, v[2]),
+        _ => Err("格式不对"),
+    };
+    match ret {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn reset_sendkey(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    let grace = match v.len() {
+        3 => Some(0),
+        4 => v[3].parse::<i64>().ok(),
+        _ => None,
+    };
+    match grace {
+        Some(grace) => {
+            let owner = msg.from.clone().unwrap();
+            match channel::INTERFACE.reset_sendkey(&id, &owner, grace) {
+                Ok(sendkey) => xml::gen_message_reply(
+                    &owner,
+                    &msg.to.unwrap(),
+                    &format!("操作成功,新SendKey:{}", sendkey),
+                ),
+                Err(err) => xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), err),
             }
-            channel_info.push_str(&format!(
-                r#"频道名:{}
-频道ID:{}
+        }
+        None => xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对"),
+    }
+}
+
+fn show_keys(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 3 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    match channel::INTERFACE.get_keys(&id, &owner) {
+        Ok(keys) => {
+            let mut key_info = String::new();
+            for key in keys {
+                key_info.push_str(&format!(
+                    r#"名称:{}
 SendKey:{}
-订阅者:{}
+创建时间:{}
+过期时间:{}
+最后使用:{}
+权限:{}
 "#,
-                &channel.name, &channel.id, &channel.sendkey, &subscribers
-            ));
-            debug!("{}", &channel_info);
+                    &key.label,
+                    &key.key,
+                    format_time(key.created),
+                    if key.expires == 0 {
+                        "不过期".to_string()
+                    } else {
+                        format_time(key.expires)
+                    },
+                    if key.last_used == 0 {
+                        "未使用".to_string()
+                    } else {
+                        format_time(key.last_used)
+                    },
+                    if key.scopes.is_empty() {
+                        "全部".to_string()
+                    } else {
+                        key.scopes.join(",")
+                    }
+                ));
+            }
+            xml::gen_message_reply(&owner, &msg.to.unwrap(), &key_info)
         }
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
     }
-    xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), &channel_info)
 }
 
-fn show_subscribe(msg: xml::UniversMessage) -> String {
+fn add_key(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() < 4 || v.len() > 6 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let expire_days = match v.get(4) {
+        Some(days) => match days.parse::<i64>() {
+            Ok(days) => days,
+            Err(_) => {
+                return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对")
+            }
+        },
+        None => 0,
+    };
+    let scopes: Vec<String> = match v.get(5) {
+        Some(scopes) => scopes.split(',').map(|s| s.to_string()).collect(),
+        None => Vec::new(),
+    };
     let owner = msg.from.clone().unwrap();
-    let user = user::INTERFACE.get_user(&owner).unwrap();
-    let mut channel_infos: String = String::new();
-    if user.subscribes.is_empty() {
-        channel_infos.push_str("没有订阅的频道");
-    } else {
-        for channel in user.subscribes {
-            match channel::INTERFACE.get_channel_by_id(&channel) {
-                Ok(chn) => {
-                    channel_infos.push_str(&format!(
-                        r#"频道名:{}
-频道ID:{}
+    match channel::INTERFACE.add_key(&id, &owner, v[3], expire_days, scopes) {
+        Ok(sendkey) => xml::gen_message_reply(
+            &owner,
+            &msg.to.unwrap(),
+            &format!("操作成功,SendKey:{}", sendkey),
+        ),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn revoke_key(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 4 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    match channel::INTERFACE.revoke_key(&id, &owner, v[3]) {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn show_scheduled(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 3 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    match channel::INTERFACE.check_role(&id, &owner, channel::Role::Admin) {
+        Ok(chn) => {
+            let list = schedule::INTERFACE.get_by_channel(&chn.id);
+            let mut reply = String::new();
+            if list.is_empty() {
+                reply.push_str("没有定时消息");
+            }
+            for scheduled in list {
+                reply.push_str(&format!(
+                    r#"ID:{}
+发送时间:{}
+标题:{}
 "#,
-                        &chn.name, &chn.id
-                    ));
-                }
-                _err => (),
+                    &scheduled.id,
+                    format_time(scheduled.send_at),
+                    &scheduled.content.title
+                ));
             }
-            debug!("{}", &channel_infos);
+            xml::gen_message_reply(&owner, &msg.to.unwrap(), &reply)
         }
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
     }
-    xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), &channel_infos)
 }
 
-fn del_channel(msg: xml::UniversMessage) -> String {
+fn cancel_scheduled(msg: xml::UniversMessage) -> String {
     let content = msg.content.unwrap();
-    let v: Vec<&str> = content.as_str().splitn(3, ' ').collect();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 4 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    let ret = match channel::INTERFACE.check_role(&id, &owner, channel::Role::Admin) {
+        Ok(chn) => schedule::INTERFACE.cancel(&chn.id, v[3]),
+        Err(err) => Err(err),
+    };
+    match ret {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn add_cron(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    // add cron 频道id 分 时 日 月 周 标题|正文
+    let v: Vec<&str> = content.as_str().splitn(9, ' ').collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 9 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let cron = v[3..8].join(" ");
+    let (text, desp) = match v[8].find('|') {
+        Some(i) => (&v[8][..i], &v[8][i + 1..]),
+        None => (v[8], ""),
+    };
+    let owner = msg.from.clone().unwrap();
+    let ret = match channel::INTERFACE.check_role(&id, &owner, channel::Role::Admin) {
+        Ok(chn) => schedule::INTERFACE.add_recurring(&chn.id, &cron, text, desp),
+        Err(err) => Err(err),
+    };
+    match ret {
+        Ok(id) => xml::gen_message_reply(&owner, &msg.to.unwrap(), &format!("操作成功,id:{}", id)),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn show_cron(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
     if v.len() != 3 {
-        xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对")
-    } else {
-        let owner = msg.from.clone().unwrap();
-        match channel::INTERFACE.delete_channel(v[2], &owner) {
-            Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
-            Err(err) => xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), err),
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    match channel::INTERFACE.check_role(&id, &owner, channel::Role::Admin) {
+        Ok(chn) => {
+            let list = schedule::INTERFACE.get_recurring_by_channel(&chn.id);
+            let mut reply = String::new();
+            if list.is_empty() {
+                reply.push_str("没有周期消息");
+            }
+            for recurring in list {
+                reply.push_str(&format!(
+                    r#"ID:{}
+cron:{}
+下次发送:{}
+标题:{}
+"#,
+                    &recurring.id,
+                    &recurring.cron,
+                    format_time(recurring.next_run),
+                    &recurring.text
+                ));
+            }
+            xml::gen_message_reply(&owner, &msg.to.unwrap(), &reply)
         }
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
     }
 }
-fn add_channel(msg: xml::UniversMessage) -> String {
+
+fn del_cron(msg: xml::UniversMessage) -> String {
     let content = msg.content.unwrap();
-    let v: Vec<&str> = content.as_str().splitn(3, ' ').collect();
-    if v.len() != 3 {
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 2);
+    if v.len() != 4 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    let ret = match channel::INTERFACE.check_role(&id, &owner, channel::Role::Admin) {
+        Ok(chn) => schedule::INTERFACE.del_recurring(&chn.id, v[3]),
+        Err(err) => Err(err),
+    };
+    match ret {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn show_feed_url(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().splitn(4, ' ').collect();
+    let id = channel_arg(&v, 2);
+    if v.len() < 3 || (v.len() == 4 && v[3] != "reset") {
         xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对")
     } else {
         let owner = msg.from.clone().unwrap();
-        match channel::INTERFACE.add_channel(v[2], &owner) {
-            Ok(cid) => {
-                xml::gen_message_reply(&owner, &msg.to.unwrap(), &format!("操作成功,id:{}", &cid))
-            }
+        match channel::INTERFACE.feed_token(&id, &owner, v.len() == 4) {
+            Ok(token) => xml::gen_message_reply(
+                &owner,
+                &msg.to.unwrap(),
+                &format!(
+                    "订阅地址:{}/channel/{}/feed.atom?token={}",
+                    CONFIG.host, &id, token
+                ),
+            ),
             Err(err) => xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), err),
         }
     }
 }
 
-fn do_subscribe(msg: xml::UniversMessage) -> String {
+fn show_history_reply(msg: xml::UniversMessage) -> String {
     let content = msg.content.unwrap();
     let v: Vec<&str> = content.as_str().splitn(2, ' ').collect();
+    let id = channel_arg(&v, 1);
     if v.len() != 2 {
-        xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对")
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let user = msg.from.clone().unwrap();
+    let chn = match channel::INTERFACE.get_channel_by_id(&id) {
+        Ok(chn) => chn,
+        Err(err) => return xml::gen_message_reply(&user, &msg.to.unwrap(), err),
+    };
+    if chn.role_of(&user).is_none() {
+        return xml::gen_message_reply(&user, &msg.to.unwrap(), "没有订阅该频道");
+    }
+    let (total, contents) = content::INTERFACE.get_channel_contents(&chn.id, 0, HISTORY_REPLY_SIZE);
+    let mut reply = String::new();
+    if contents.is_empty() {
+        reply.push_str("没有历史消息");
     } else {
-        let owner = msg.from.clone().unwrap();
-        match channel::INTERFACE.subscribe(v[1], &owner) {
-            Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
-            Err(err) => xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), err),
+        for (id, content) in contents {
+            reply.push_str(&format!(
+                "<a href=\"{}/content/{}\">{}</a> {}\n",
+                CONFIG.host,
+                id,
+                content.display_title(),
+                format_time(content.time)
+            ));
+        }
+        reply.push_str(&format!(
+            "共{}条 <a href=\"{}/channel/{}/history\">查看全部</a>",
+            total, CONFIG.host, chn.id
+        ));
+    }
+    xml::gen_message_reply(&user, &msg.to.unwrap(), &reply)
+}
+
+fn set_digest(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 1);
+    if v.len() != 3 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let mode = match v[2] {
+        "hourly" => Some(user::DigestMode::Hourly),
+        "daily" => Some(user::DigestMode::Daily),
+        "off" => None,
+        _ => return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对"),
+    };
+    let owner = msg.from.clone().unwrap();
+    match user::INTERFACE.set_subscribe_option(&owner, &id, |option| option.digest = mode) {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+// 解析 30m、2h、1d 格式的时长，返回秒数
+fn parse_duration(duration: &str) -> Option<i64> {
+    let (num, unit) = duration.split_at(duration.char_indices().last()?.0);
+    let secs = match unit {
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    match num.parse::<i64>() {
+        Ok(num) if num > 0 => num.checked_mul(secs),
+        _ => None,
+    }
+}
+
+fn mute_channel(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 1);
+    let owner = msg.from.clone().unwrap();
+    let until = match v.len() {
+        2 => 0,
+        3 => match parse_duration(v[2])
+            .and_then(|secs| chrono::Local::now().timestamp().checked_add(secs))
+        {
+            Some(until) => until,
+            None => return xml::gen_message_reply(&owner, &msg.to.unwrap(), "时长格式不对"),
+        },
+        _ => return xml::gen_message_reply(&owner, &msg.to.unwrap(), "格式不对"),
+    };
+    match user::INTERFACE
+        .set_subscribe_option(&owner, &id, |option| option.muted_until = Some(until))
+    {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn unmute_channel(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let id = channel_arg(&v, 1);
+    let owner = msg.from.clone().unwrap();
+    if v.len() != 2 {
+        return xml::gen_message_reply(&owner, &msg.to.unwrap(), "格式不对");
+    }
+    match user::INTERFACE.set_subscribe_option(&owner, &id, |option| option.muted_until = None) {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn set_filter(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    // 关键词中可能有空格
+    let v: Vec<&str> = content.as_str().splitn(4, ' ').collect();
+    let id = channel_arg(&v, 1);
+    let owner = msg.from.clone().unwrap();
+    if v.len() < 2 {
+        return xml::gen_message_reply(&owner, &msg.to.unwrap(), "格式不对");
+    }
+    // 只有频道id时查看过滤条件
+    if v.len() == 2 {
+        let reply = match user::INTERFACE.get_user(&owner) {
+            Ok(user) if user.subscribes.contains(&id) => {
+                let filters = user.get_option(&id).filters;
+                if filters.is_empty() {
+                    "没有设置过滤条件".to_string()
+                } else {
+                    filters
+                        .iter()
+                        .enumerate()
+                        .map(|(i, filter)| format!("{}. {}", i + 1, filter.describe()))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                }
+            }
+            Ok(_) => "没有订阅该频道".to_string(),
+            Err(err) => err.to_string(),
+        };
+        return xml::gen_message_reply(&owner, &msg.to.unwrap(), &reply);
+    }
+    let ret = match (v[2], v.get(3)) {
+        ("include", Some(pattern)) | ("exclude", Some(pattern)) => {
+            let kind = if v[2] == "include" {
+                user::FilterKind::Include
+            } else {
+                user::FilterKind::Exclude
+            };
+            match user::Filter::parse(kind, pattern) {
+                Ok(filter) => user::INTERFACE
+                    .set_subscribe_option(&owner, &id, |option| option.filters.push(filter)),
+                Err(err) => Err(err),
+            }
+        }
+        ("del", Some(index)) => match index.trim().parse::<usize>() {
+            Ok(index) if index > 0 => {
+                let mut found = false;
+                let ret = user::INTERFACE.set_subscribe_option(&owner, &id, |option| {
+                    if index <= option.filters.len() {
+                        option.filters.remove(index - 1);
+                        found = true;
+                    }
+                });
+                match ret {
+                    Ok(_) if !found => Err("没找到对应的过滤条件"),
+                    ret => ret,
+                }
+            }
+            _ => Err("格式不对"),
+        },
+        ("clear", None) => {
+            user::INTERFACE.set_subscribe_option(&owner, &id, |option| option.filters.clear())
+        }
+        _ => Err("格式不对"),
+    };
+    match ret {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn set_quiet(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().split_whitespace().collect();
+    let owner = msg.from.clone().unwrap();
+    let quiet = match v.len() {
+        // 只发送quiet时查看当前设置
+        1 => {
+            let reply = match user::INTERFACE.get_user(&owner) {
+                Ok(user) => match user.quiet {
+                    Some(quiet) => format!("免打扰时段:{}", quiet.describe()),
+                    None => "没有设置免打扰时段".to_string(),
+                },
+                Err(err) => err.to_string(),
+            };
+            return xml::gen_message_reply(&owner, &msg.to.unwrap(), &reply);
+        }
+        2 if v[1] == "off" => None,
+        3 | 4 => match user::QuietHours::parse(v[1], v[2], v.get(3).cloned()) {
+            Ok(quiet) => Some(quiet),
+            Err(err) => return xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+        },
+        _ => return xml::gen_message_reply(&owner, &msg.to.unwrap(), "格式不对"),
+    };
+    match user::INTERFACE.set_quiet(&owner, quiet) {
+        Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+        Err(err) => xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+    }
+}
+
+fn do_subscribe(msg: xml::UniversMessage) -> String {
+    let content = msg.content.unwrap();
+    let v: Vec<&str> = content.as_str().splitn(3, ' ').collect();
+    let id = channel_arg(&v, 1);
+    if v.len() < 2 {
+        return xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对");
+    }
+    let owner = msg.from.clone().unwrap();
+    // 标签为*时清空标签，重新接收所有消息
+    let tags = v.get(2).map(|tags| match tags.trim() {
+        "*" => Vec::new(),
+        tags => content::parse_tags(tags),
+    });
+    let subscribed = user::INTERFACE
+        .get_user(&owner)
+        .map(|user| user.subscribes.contains(&id))
+        .unwrap_or(false);
+    // 已经订阅时只更新标签
+    if !subscribed || tags.is_none() {
+        let request_tags = tags.clone().unwrap_or_default();
+        match channel::INTERFACE.subscribe(&id, &owner, &request_tags) {
+            Ok(true) => (),
+            Ok(false) => {
+                return xml::gen_message_reply(
+                    &owner,
+                    &msg.to.unwrap(),
+                    "已提交订阅申请，请等待频道创建者审核",
+                )
+            }
+            Err(err) => return xml::gen_message_reply(&owner, &msg.to.unwrap(), err),
+        }
+    }
+    if let Some(tags) = tags {
+        if let Err(err) =
+            user::INTERFACE.set_subscribe_option(&owner, &id, |option| option.tags = tags)
+        {
+            return xml::gen_message_reply(&owner, &msg.to.unwrap(), err);
         }
     }
+    match channel::INTERFACE.get_channel_by_id(&id) {
+        Ok(chn) if !subscribed => {
+            xml::gen_message_reply(&owner, &msg.to.unwrap(), &subscribed_reply(&chn))
+        }
+        _ => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
+    }
 }
 
 fn do_unsubscribe(msg: xml::UniversMessage) -> String {
     let content = msg.content.unwrap();
     let v: Vec<&str> = content.as_str().splitn(2, ' ').collect();
+    let id = channel_arg(&v, 1);
     if v.len() != 2 {
         xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), "格式不对")
     } else {
         let owner = msg.from.clone().unwrap();
-        match channel::INTERFACE.unsubscribe(v[1], &owner) {
+        match channel::INTERFACE.unsubscribe(&id, &owner) {
             Ok(_) => xml::gen_message_reply(&owner, &msg.to.unwrap(), "操作成功"),
             Err(err) => xml::gen_message_reply(&msg.from.unwrap(), &msg.to.unwrap(), err),
         }
@@ -307,6 +2189,81 @@ fn wx_post(query: web::Query<AuthInfo>, message: String) -> impl Responder {
                         HttpResponse::Ok().body(del_channel(msg))
                     } else if content.as_str().starts_with("create channel") {
                         HttpResponse::Ok().body(add_channel(msg))
+                    } else if content.as_str().starts_with("private channel") {
+                        HttpResponse::Ok().body(set_private(msg))
+                    } else if content.as_str().starts_with("rename channel")
+                        || content.as_str().starts_with("describe channel")
+                    {
+                        HttpResponse::Ok().body(edit_channel(msg))
+                    } else if content.as_str().starts_with("handle channel") {
+                        HttpResponse::Ok().body(set_handle(msg))
+                    } else if content.as_str().starts_with("list channel") {
+                        HttpResponse::Ok().body(set_listed(msg))
+                    } else if content.as_str().starts_with("search") {
+                        HttpResponse::Ok().body(search_channel(msg))
+                    } else if content.as_str().starts_with("visibility channel") {
+                        HttpResponse::Ok().body(set_visibility(msg))
+                    } else if content.as_str().starts_with("show requests") {
+                        HttpResponse::Ok().body(show_requests(msg))
+                    } else if content.as_str().starts_with("approve") {
+                        HttpResponse::Ok().body(review_request(msg, true))
+                    } else if content.as_str().starts_with("reject") {
+                        HttpResponse::Ok().body(review_request(msg, false))
+                    } else if content.as_str().starts_with("create invite") {
+                        HttpResponse::Ok().body(add_invite(msg))
+                    } else if content.as_str().starts_with("show invites") {
+                        HttpResponse::Ok().body(show_invites(msg))
+                    } else if content.as_str().starts_with("revoke invite") {
+                        HttpResponse::Ok().body(revoke_invite(msg))
+                    } else if content.as_str().starts_with("join") {
+                        HttpResponse::Ok().body(join_channel(msg))
+                    } else if content.as_str().starts_with("grant admin")
+                        || content.as_str().starts_with("revoke admin")
+                    {
+                        HttpResponse::Ok().body(set_admin(msg))
+                    } else if content.as_str().starts_with("transfer") {
+                        HttpResponse::Ok().body(transfer_channel(msg))
+                    } else if content.as_str().starts_with("accept transfer")
+                        || content.as_str().starts_with("decline transfer")
+                    {
+                        HttpResponse::Ok().body(confirm_transfer(msg))
+                    } else if content.as_str().starts_with("kick ")
+                        || content.as_str().starts_with("ban ")
+                        || content.as_str().starts_with("unban ")
+                    {
+                        HttpResponse::Ok().body(manage_subscriber(msg))
+                    } else if content.as_str().starts_with("reset sendkey") {
+                        HttpResponse::Ok().body(reset_sendkey(msg))
+                    } else if content.as_str().starts_with("show keys") {
+                        HttpResponse::Ok().body(show_keys(msg))
+                    } else if content.as_str().starts_with("add key") {
+                        HttpResponse::Ok().body(add_key(msg))
+                    } else if content.as_str().starts_with("revoke key") {
+                        HttpResponse::Ok().body(revoke_key(msg))
+                    } else if content.as_str().starts_with("show scheduled") {
+                        HttpResponse::Ok().body(show_scheduled(msg))
+                    } else if content.as_str().starts_with("cancel scheduled") {
+                        HttpResponse::Ok().body(cancel_scheduled(msg))
+                    } else if content.as_str().starts_with("add cron") {
+                        HttpResponse::Ok().body(add_cron(msg))
+                    } else if content.as_str().starts_with("show cron") {
+                        HttpResponse::Ok().body(show_cron(msg))
+                    } else if content.as_str().starts_with("del cron") {
+                        HttpResponse::Ok().body(del_cron(msg))
+                    } else if content.as_str().starts_with("feed channel") {
+                        HttpResponse::Ok().body(show_feed_url(msg))
+                    } else if content.as_str().starts_with("digest") {
+                        HttpResponse::Ok().body(set_digest(msg))
+                    } else if content.as_str().starts_with("mute") {
+                        HttpResponse::Ok().body(mute_channel(msg))
+                    } else if content.as_str().starts_with("unmute") {
+                        HttpResponse::Ok().body(unmute_channel(msg))
+                    } else if content.as_str().starts_with("filter") {
+                        HttpResponse::Ok().body(set_filter(msg))
+                    } else if content.as_str().starts_with("quiet") {
+                        HttpResponse::Ok().body(set_quiet(msg))
+                    } else if content.as_str().starts_with("history") {
+                        HttpResponse::Ok().body(show_history_reply(msg))
                     } else if content.as_str().starts_with("subscribe") {
                         HttpResponse::Ok().body(do_subscribe(msg))
                     } else if content.as_str().starts_with("unsubscribe") {
@@ -328,12 +2285,39 @@ fn wx_post(query: web::Query<AuthInfo>, message: String) -> impl Responder {
                                 let _ = user::INTERFACE.add_user(&uid);
                             }
                         }
+                        // 扫描频道二维码关注时直接订阅频道
+                        let mut reply = CONFIG.welcome.clone();
+                        if let Some(scene_reply) = msg
+                            .event_key
+                            .as_ref()
+                            .and_then(|key| key.strip_prefix("qrscene_"))
+                            .and_then(|scene| handle_scene(scene, &uid))
+                        {
+                            reply.push('\n');
+                            reply.push_str(&scene_reply);
+                        }
                         HttpResponse::Ok().body(xml::gen_message_reply(
                             &uid,
                             &msg.to.unwrap(),
-                            &CONFIG.welcome,
+                            &reply,
                         ))
                     }
+                    // 已关注的用户扫描频道二维码
+                    "SCAN" => {
+                        let uid = msg.from.unwrap().clone();
+                        match msg
+                            .event_key
+                            .as_ref()
+                            .and_then(|scene| handle_scene(scene, &uid))
+                        {
+                            Some(reply) => HttpResponse::Ok().body(xml::gen_message_reply(
+                                &uid,
+                                &msg.to.unwrap(),
+                                &reply,
+                            )),
+                            None => HttpResponse::Ok().finish(),
+                        }
+                    }
                     _ => HttpResponse::Ok().finish(),
                 },
                 _ => HttpResponse::Ok().finish(),
@@ -360,14 +2344,36 @@ fn main() {
         *CONFIG_FILE.lock().unwrap() = c.to_string();
     }
 
+    std::thread::spawn(run_scheduler);
+
     info!("Listening on http://{}", CONFIG.listen);
 
     HttpServer::new(|| {
         App::new()
             .route("/wx", web::get().to(wx_auth))
             .route("/wx", web::post().to(wx_post))
-            .route("/sub", web::get().to(wx_sub))
+            .service(
+                web::resource("/sub")
+                    .data(web::PayloadConfig::new(
+                        CONFIG.attachment_max_size * CONFIG.attachment_max_count + 64 * 1024,
+                    ))
+                    .route(web::get().to(wx_sub))
+                    .route(web::post().to(wx_sub_upload)),
+            )
+            .route("/scheduled", web::get().to(wx_scheduled))
+            .route("/cancel", web::get().to(wx_cancel))
+            .route("/edit", web::get().to(wx_edit))
+            .route("/recall", web::get().to(wx_recall))
+            .route("/admin/reset_sendkey", web::get().to(admin_reset_sendkey))
+            .route("/admin/cron/list", web::get().to(admin_cron_list))
+            .route("/admin/cron/add", web::get().to(admin_cron_add))
+            .route("/admin/cron/del", web::get().to(admin_cron_del))
             .route("/content/{id}", web::get().to(show_content))
+            .route("/blob/{id}", web::get().to(show_blob))
+            .route("/digest/{id}", web::get().to(show_digest))
+            .route("/channels", web::get().to(show_directory))
+            .route("/channel/{id}/history", web::get().to(show_history))
+            .route("/channel/{id}/feed.atom", web::get().to(show_feed))
     })
     .bind(&CONFIG.listen)
     .unwrap()
@@ -0,0 +1,83 @@
+// 简单的multipart/form-data解析
+
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+// 从Content-Type中取出boundary
+pub fn get_boundary(content_type: &str) -> Option<String> {
+    if !content_type.starts_with("multipart/form-data") {
+        return None;
+    }
+    content_type
+        .split(';')
+        .map(|param| param.trim())
+        .find(|param| param.starts_with("boundary="))
+        .map(|param| param["boundary=".len()..].trim_matches('"').to_string())
+}
+
+fn find(data: &[u8], pattern: &[u8], start: usize) -> Option<usize> {
+    if start > data.len() || pattern.len() > data.len() - start {
+        return None;
+    }
+    (start..=data.len() - pattern.len()).find(|&i| &data[i..i + pattern.len()] == pattern)
+}
+
+// 解析Content-Disposition中的参数
+fn get_param(disposition: &str, key: &str) -> Option<String> {
+    disposition
+        .split(';')
+        .map(|param| param.trim())
+        .find(|param| param.starts_with(&format!("{}=", key)))
+        .map(|param| param[key.len() + 1..].trim_matches('"').to_string())
+}
+
+pub fn parse(body: &[u8], boundary: &str) -> Result<Vec<Part>, &'static str> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut ret = Vec::new();
+    let mut pos = match find(body, &delimiter, 0) {
+        Some(pos) => pos + delimiter.len(),
+        None => return Err("格式不对"),
+    };
+    loop {
+        // 结束标记
+        if body[pos..].starts_with(b"--") {
+            break;
+        }
+        if !body[pos..].starts_with(b"\r\n") {
+            return Err("格式不对");
+        }
+        pos += 2;
+        let header_end = match find(body, b"\r\n\r\n", pos) {
+            Some(end) => end,
+            None => return Err("格式不对"),
+        };
+        let headers = String::from_utf8_lossy(&body[pos..header_end]).to_string();
+        pos = header_end + 4;
+        let mut end_delimiter = b"\r\n".to_vec();
+        end_delimiter.extend_from_slice(&delimiter);
+        let data_end = match find(body, &end_delimiter, pos) {
+            Some(end) => end,
+            None => return Err("格式不对"),
+        };
+        let data = body[pos..data_end].to_vec();
+        pos = data_end + end_delimiter.len();
+
+        let disposition = headers.lines().find(|line| {
+            line.to_ascii_lowercase()
+                .starts_with("content-disposition:")
+        });
+        if let Some(disposition) = disposition {
+            if let Some(name) = get_param(disposition, "name") {
+                ret.push(Part {
+                    name,
+                    filename: get_param(disposition, "filename"),
+                    data,
+                });
+            }
+        }
+    }
+    Ok(ret)
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const STORE: &str = "quota";
+
+lazy_static! {
+    pub static ref INTERFACE: RateLimitInterface = RateLimitInterface::new();
+}
+
+// 令牌桶
+struct Bucket {
+    tokens: f64,
+    updated: f64,
+}
+
+// 每天的模板消息用量
+#[derive(Debug, Deserialize, Clone, Serialize, Default)]
+struct DailyUsage {
+    total: u32,
+    channels: HashMap<String, u32>,
+}
+
+pub struct RateLimitInterface {
+    // 按照日期存储的 date/用量，重启后不丢失当天的用量
+    storage: super::storage::SingleKvStorage,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+fn now_secs() -> f64 {
+    let now = chrono::Local::now();
+    now.timestamp() as f64 + f64::from(now.timestamp_subsec_millis()) / 1000.0
+}
+
+// 到明天零点的秒数
+fn secs_to_tomorrow() -> u64 {
+    let now = chrono::Local::now();
+    let tomorrow = now.date().succ().and_hms(0, 0, 0);
+    (tomorrow - now).num_seconds().max(1) as u64
+}
+
+// 全账号每日模板消息额度，单个频道最多只能使用其中一部分
+fn exceeds_budget(usage: &DailyUsage, channel: &str) -> bool {
+    let config = &super::CONFIG;
+    if config.template_daily_budget == 0 {
+        return false;
+    }
+    let channel_used = usage.channels.get(channel).cloned().unwrap_or(0);
+    let channel_budget =
+        config.template_daily_budget * config.template_channel_share.min(100) / 100;
+    usage.total >= config.template_daily_budget || channel_used >= channel_budget
+}
+
+impl RateLimitInterface {
+    pub fn new() -> RateLimitInterface {
+        RateLimitInterface {
+            storage: super::storage::SingleKvStorage::new(&super::CONFIG.db_path, STORE),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 检查sendkey和频道的发送频率，以及当天是否还有模板消息额度
+    // 超出限制时返回需要等待的秒数
+    pub fn acquire(&self, channel: &str, sendkey: &str) -> Result<(), u64> {
+        let config = &super::CONFIG;
+        let limits = [
+            (
+                format!("key_minute:{}", sendkey),
+                config.rate_key_per_minute,
+                60.0,
+            ),
+            (
+                format!("key_day:{}", sendkey),
+                config.rate_key_per_day,
+                86400.0,
+            ),
+            (
+                format!("channel_minute:{}", channel),
+                config.rate_channel_per_minute,
+                60.0,
+            ),
+            (
+                format!("channel_day:{}", channel),
+                config.rate_channel_per_day,
+                86400.0,
+            ),
+        ];
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = now_secs();
+        // 先检查所有限制，全部通过后再扣除
+        let mut retry_after = 0;
+        for (name, limit, period) in limits.iter() {
+            if *limit == 0 {
+                continue;
+            }
+            let capacity = f64::from(*limit);
+            let rate = capacity / period;
+            let bucket = buckets.entry(name.clone()).or_insert(Bucket {
+                tokens: capacity,
+                updated: now,
+            });
+            bucket.tokens = (bucket.tokens + (now - bucket.updated) * rate).min(capacity);
+            bucket.updated = now;
+            if bucket.tokens < 1.0 {
+                let wait = ((1.0 - bucket.tokens) / rate).ceil() as u64;
+                retry_after = retry_after.max(wait.max(1));
+            }
+        }
+        if retry_after > 0 {
+            debug!("rate limited, retry after:{}", retry_after);
+            return Err(retry_after);
+        }
+
+        // 额度在实际发送模板消息时扣除
+        let date = chrono::Local::today().format("%Y%m%d").to_string();
+        let usage: DailyUsage = match self.storage.get_single(&date) {
+            Some(usage_string) => serde_json::from_str(&usage_string).unwrap(),
+            None => DailyUsage::default(),
+        };
+        if exceeds_budget(&usage, channel) {
+            debug!("template budget exhausted:{:?}", usage);
+            return Err(secs_to_tomorrow());
+        }
+
+        for (name, limit, _) in limits.iter() {
+            if *limit == 0 {
+                continue;
+            }
+            if let Some(bucket) = buckets.get_mut(name) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    // 发送一条模板消息前扣除额度，额度用完时返回false
+    pub fn charge_template(&self, channel: &str) -> bool {
+        let date = chrono::Local::today().format("%Y%m%d").to_string();
+        self.storage.update_single(&date, |value| {
+            let mut usage: DailyUsage = match value {
+                Some(usage_string) => serde_json::from_str(usage_string).unwrap(),
+                None => DailyUsage::default(),
+            };
+            if exceeds_budget(&usage, channel) {
+                return false;
+            }
+            usage.total += 1;
+            *usage.channels.entry(channel.to_string()).or_insert(0) += 1;
+            *value = Some(serde_json::to_string(&usage).unwrap());
+            true
+        })
+    }
+}
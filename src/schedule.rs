@@ -0,0 +1,260 @@
+use super::content::Content;
+
+// 等待发送的定时消息
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct Scheduled {
+    pub id: String,
+    pub channel: String,
+    // 提交时使用的sendkey，发送时用于频率限制
+    pub sendkey: String,
+    pub send_at: i64,
+    pub content: Content,
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+}
+
+// 按cron表达式周期发送的消息
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct Recurring {
+    pub id: String,
+    pub channel: String,
+    pub cron: String,
+    // 标题和正文模板，发送时替换{date}和{time}
+    pub text: String,
+    pub desp: String,
+    pub next_run: i64,
+}
+
+const STORE: &str = "schedule";
+const STORE_RECURRING: &str = "schedule_recurring";
+
+lazy_static! {
+    pub static ref INTERFACE: ScheduleInterface = ScheduleInterface::new();
+}
+
+pub struct ScheduleInterface {
+    // id/scheduled
+    storage: super::storage::SingleKvStorage,
+    // id/recurring
+    storage_recurring: super::storage::SingleKvStorage,
+}
+
+impl ScheduleInterface {
+    pub fn new() -> ScheduleInterface {
+        ScheduleInterface {
+            storage: super::storage::SingleKvStorage::new(&super::CONFIG.db_path, STORE),
+            storage_recurring: super::storage::SingleKvStorage::new(
+                &super::CONFIG.db_path,
+                STORE_RECURRING,
+            ),
+        }
+    }
+
+    fn put_scheduled(&self, scheduled: &Scheduled) {
+        let json_string = serde_json::to_string(scheduled).unwrap();
+        self.storage
+            .put_single(&scheduled.id, &rkv::Value::Json(&json_string));
+    }
+
+    fn get_all(&self) -> Vec<Scheduled> {
+        let env = self.storage.env.read().unwrap();
+        let reader = env.read().unwrap();
+        let mut iter = self.storage.single.iter_start(&reader).unwrap();
+        let mut ret = Vec::new();
+        while let Some(Ok((_, scheduled))) = iter.next() {
+            if let Some(rkv::Value::Json(scheduled)) = scheduled {
+                ret.push(serde_json::from_str(scheduled).unwrap());
+            }
+        }
+        ret
+    }
+
+    // 返回定时消息id
+    pub fn add_scheduled(
+        &self,
+        sendkey: &str,
+        send_at: i64,
+        content: Content,
+        idempotency_key: Option<String>,
+    ) -> String {
+        let id = uuid::Uuid::new_v4().to_simple().to_string();
+        let scheduled = Scheduled {
+            id: id.clone(),
+            channel: content.channel.clone(),
+            sendkey: sendkey.to_string(),
+            send_at,
+            content,
+            idempotency_key,
+        };
+        debug!("new scheduled:{:?}", scheduled);
+        self.put_scheduled(&scheduled);
+        id
+    }
+
+    // 按发送时间排序
+    pub fn get_by_channel(&self, channel: &str) -> Vec<Scheduled> {
+        let mut ret: Vec<Scheduled> = self
+            .get_all()
+            .into_iter()
+            .filter(|scheduled| scheduled.channel == channel)
+            .collect();
+        ret.sort_by_key(|scheduled| scheduled.send_at);
+        ret
+    }
+
+    // 查找还没发送的相同幂等键的定时消息
+    pub fn find_by_key(&self, channel: &str, key: &str) -> Option<String> {
+        self.get_all()
+            .into_iter()
+            .find(|scheduled| {
+                scheduled.channel == channel && scheduled.idempotency_key.as_deref() == Some(key)
+            })
+            .map(|scheduled| scheduled.id)
+    }
+
+    pub fn cancel(&self, channel: &str, id: &str) -> Result<bool, &str> {
+        match self.storage.get_single(id) {
+            Some(scheduled_string) => {
+                let scheduled: Scheduled = serde_json::from_str(&scheduled_string).unwrap();
+                if scheduled.channel != channel {
+                    return Err("定时消息不属于该频道");
+                }
+                for blob in &scheduled.content.attachments {
+                    super::blob::INTERFACE.del_blob(blob);
+                }
+                self.storage.del_single(id);
+                Ok(true)
+            }
+            None => Err("没找到对应的定时消息"),
+        }
+    }
+
+    // 获取到了发送时间的消息
+    pub fn get_due(&self, now: i64) -> Vec<Scheduled> {
+        let mut ret: Vec<Scheduled> = self
+            .get_all()
+            .into_iter()
+            .filter(|scheduled| scheduled.send_at <= now)
+            .collect();
+        ret.sort_by_key(|scheduled| scheduled.send_at);
+        ret
+    }
+
+    pub fn postpone(&self, scheduled: &mut Scheduled, send_at: i64) {
+        scheduled.send_at = send_at;
+        self.put_scheduled(scheduled);
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.storage.del_single(id);
+    }
+
+    fn put_recurring(&self, recurring: &Recurring) {
+        let json_string = serde_json::to_string(recurring).unwrap();
+        self.storage_recurring
+            .put_single(&recurring.id, &rkv::Value::Json(&json_string));
+    }
+
+    fn get_all_recurring(&self) -> Vec<Recurring> {
+        let env = self.storage_recurring.env.read().unwrap();
+        let reader = env.read().unwrap();
+        let mut iter = self.storage_recurring.single.iter_start(&reader).unwrap();
+        let mut ret = Vec::new();
+        while let Some(Ok((_, recurring))) = iter.next() {
+            if let Some(rkv::Value::Json(recurring)) = recurring {
+                ret.push(serde_json::from_str(recurring).unwrap());
+            }
+        }
+        ret
+    }
+
+    // 返回周期消息id
+    pub fn add_recurring(
+        &self,
+        channel: &str,
+        cron: &str,
+        text: &str,
+        desp: &str,
+    ) -> Result<String, &str> {
+        let expr = super::cron::CronExpr::parse(cron)?;
+        let next_run = match expr.next_after(chrono::Local::now().timestamp()) {
+            Some(next_run) => next_run,
+            None => return Err("cron表达式不会触发"),
+        };
+        let id = uuid::Uuid::new_v4().to_simple().to_string();
+        let recurring = Recurring {
+            id: id.clone(),
+            channel: channel.to_string(),
+            cron: cron.to_string(),
+            text: text.to_string(),
+            desp: desp.to_string(),
+            next_run,
+        };
+        debug!("new recurring:{:?}", recurring);
+        self.put_recurring(&recurring);
+        Ok(id)
+    }
+
+    pub fn get_recurring_by_channel(&self, channel: &str) -> Vec<Recurring> {
+        let mut ret: Vec<Recurring> = self
+            .get_all_recurring()
+            .into_iter()
+            .filter(|recurring| recurring.channel == channel)
+            .collect();
+        ret.sort_by_key(|recurring| recurring.next_run);
+        ret
+    }
+
+    pub fn del_recurring(&self, channel: &str, id: &str) -> Result<bool, &str> {
+        match self.storage_recurring.get_single(id) {
+            Some(recurring_string) => {
+                let recurring: Recurring = serde_json::from_str(&recurring_string).unwrap();
+                if recurring.channel != channel {
+                    return Err("周期消息不属于该频道");
+                }
+                self.storage_recurring.del_single(id);
+                Ok(true)
+            }
+            None => Err("没找到对应的周期消息"),
+        }
+    }
+
+    pub fn get_due_recurring(&self, now: i64) -> Vec<Recurring> {
+        self.get_all_recurring()
+            .into_iter()
+            .filter(|recurring| recurring.next_run <= now)
+            .collect()
+    }
+
+    // 计算下一次发送时间，不会再触发时删除
+    pub fn advance_recurring(&self, recurring: &mut Recurring, now: i64) {
+        let next_run = super::cron::CronExpr::parse(&recurring.cron)
+            .ok()
+            .and_then(|expr| expr.next_after(now));
+        match next_run {
+            Some(next_run) => {
+                recurring.next_run = next_run;
+                self.put_recurring(recurring);
+            }
+            None => self.storage_recurring.del_single(&recurring.id),
+        }
+    }
+
+    // 发送出错时提前下次发送的时间
+    pub fn postpone_recurring(&self, recurring: &mut Recurring, send_at: i64) {
+        if send_at < recurring.next_run {
+            recurring.next_run = send_at;
+            self.put_recurring(recurring);
+        }
+    }
+
+    // 删除频道的所有定时和周期消息
+    pub fn clean_channel(&self, channel: &str) {
+        for scheduled in self.get_by_channel(channel) {
+            let _ = self.cancel(channel, &scheduled.id);
+        }
+        for recurring in self.get_recurring_by_channel(channel) {
+            self.storage_recurring.del_single(&recurring.id);
+        }
+    }
+}
@@ -1,7 +1,10 @@
-use rkv::{Manager, Rkv, StoreOptions, Value};
+use rkv::{Manager, Readable, Rkv, StoreOptions, Value, Writer};
 
 use std::sync::{Arc, RwLock};
 
+// 同一个环境下可打开的数据库数量
+const MAX_DBS: u32 = 32;
+
 pub struct SingleKvStorage {
     pub env: Arc<RwLock<Rkv>>,
     pub single: rkv::store::single::SingleStore,
@@ -14,12 +17,12 @@ impl SingleKvStorage {
         let created_arc = Manager::singleton()
             .write()
             .unwrap()
-            .get_or_create(path, Rkv::new)
+            .get_or_create(path, |p| Rkv::with_capacity(p, MAX_DBS))
             .unwrap();
         let created_arc2 = Manager::singleton()
             .write()
             .unwrap()
-            .get_or_create(path, Rkv::new)
+            .get_or_create(path, |p| Rkv::with_capacity(p, MAX_DBS))
             .unwrap();
         let k = created_arc2.read().unwrap();
         let store = k.open_single(db, StoreOptions::create()).unwrap();
@@ -52,4 +55,39 @@ impl SingleKvStorage {
         self.single.delete(&mut writer, key).unwrap();
         writer.commit().unwrap();
     }
+
+    // 在事务中读取json数据，可以和其他store共用一个写事务
+    pub fn get_json<T: Readable>(&self, reader: &T, key: &str) -> Option<String> {
+        match self.single.get(reader, key).unwrap() {
+            Some(Value::Json(json_string)) => Some(json_string.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn put_json(&self, writer: &mut Writer, key: &str, json_string: &str) {
+        self.single
+            .put(writer, key, &Value::Json(json_string))
+            .unwrap();
+    }
+
+    // 不存在时忽略
+    pub fn del_json(&self, writer: &mut Writer, key: &str) {
+        if self.single.get(writer, key).unwrap().is_some() {
+            self.single.delete(writer, key).unwrap();
+        }
+    }
+
+    // 在同一个写事务中读取并修改一条json数据，改成None时删除
+    pub fn update_single<T>(&self, key: &str, f: impl FnOnce(&mut Option<String>) -> T) -> T {
+        let env = self.env.read().unwrap();
+        let mut writer = env.write().unwrap();
+        let mut value = self.get_json(&writer, key);
+        let ret = f(&mut value);
+        match value {
+            Some(json_string) => self.put_json(&mut writer, key, &json_string),
+            None => self.del_json(&mut writer, key),
+        }
+        writer.commit().unwrap();
+        ret
+    }
 }
@@ -1,11 +1,233 @@
 use serde_json;
 
+use std::collections::HashMap;
+
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestMode {
+    Hourly,
+    Daily,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind {
+    Include,
+    Exclude,
+}
+
+// 关键词过滤，匹配标题和正文
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub pattern: String,
+    // 为false时按关键词匹配，忽略大小写
+    #[serde(default)]
+    pub regex: bool,
+}
+
+impl Filter {
+    // 用 /pattern/ 表示正则表达式
+    pub fn parse(kind: FilterKind, pattern: &str) -> Result<Filter, &'static str> {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err("关键词不能为空");
+        }
+        if pattern.len() > 2 && pattern.starts_with('/') && pattern.ends_with('/') {
+            let pattern = &pattern[1..pattern.len() - 1];
+            if regex::Regex::new(pattern).is_err() {
+                return Err("正则表达式不对");
+            }
+            return Ok(Filter {
+                kind,
+                pattern: pattern.to_string(),
+                regex: true,
+            });
+        }
+        Ok(Filter {
+            kind,
+            pattern: pattern.to_string(),
+            regex: false,
+        })
+    }
+
+    pub fn matches(&self, text: &str, desp: &str) -> bool {
+        if self.regex {
+            match regex::Regex::new(&self.pattern) {
+                Ok(re) => re.is_match(text) || re.is_match(desp),
+                Err(_) => false,
+            }
+        } else {
+            let pattern = self.pattern.to_lowercase();
+            text.to_lowercase().contains(&pattern) || desp.to_lowercase().contains(&pattern)
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let kind = match self.kind {
+            FilterKind::Include => "include",
+            FilterKind::Exclude => "exclude",
+        };
+        if self.regex {
+            format!("{} /{}/", kind, self.pattern)
+        } else {
+            format!("{} {}", kind, self.pattern)
+        }
+    }
+}
+
+// 每个订阅的设置
+#[derive(Debug, Deserialize, Clone, Serialize, Default)]
+pub struct SubscribeOption {
+    // 摘要模式，为空表示每条消息单独推送
+    #[serde(default)]
+    pub digest: Option<DigestMode>,
+    // 静音截止时间，为空表示没有静音，0表示一直静音
+    #[serde(default)]
+    pub muted_until: Option<i64>,
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    // 只接收带有这些标签的消息，为空表示接收全部
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl SubscribeOption {
+    pub fn is_muted(&self, timestamp: i64) -> bool {
+        match self.muted_until {
+            Some(0) => true,
+            Some(until) => timestamp < until,
+            None => false,
+        }
+    }
+
+    pub fn accepts_tags(&self, tags: &[String]) -> bool {
+        self.tags.is_empty() || self.tags.iter().any(|tag| tags.contains(tag))
+    }
+
+    // 有include过滤时至少匹配一个，且不能匹配任何exclude过滤
+    pub fn accepts(&self, text: &str, desp: &str) -> bool {
+        let mut includes = self
+            .filters
+            .iter()
+            .filter(|filter| filter.kind == FilterKind::Include)
+            .peekable();
+        if includes.peek().is_some() && !includes.any(|filter| filter.matches(text, desp)) {
+            return false;
+        }
+        !self
+            .filters
+            .iter()
+            .any(|filter| filter.kind == FilterKind::Exclude && filter.matches(text, desp))
+    }
+}
+
+// 免打扰时段，期间普通消息暂缓推送
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct QuietHours {
+    // 一天中的分钟数，start大于end时表示跨过零点
+    pub start: u32,
+    pub end: u32,
+    // 相对UTC的偏移分钟数
+    pub offset: i32,
+}
+
+// 解析 HH:MM 格式的时间，返回一天中的分钟数
+fn parse_minutes(time: &str) -> Result<u32, &'static str> {
+    let v: Vec<&str> = time.split(':').collect();
+    if v.len() != 2 {
+        return Err("时间格式不对");
+    }
+    match (v[0].parse::<u32>(), v[1].parse::<u32>()) {
+        (Ok(hour), Ok(minute)) if hour < 24 && minute < 60 => Ok(hour * 60 + minute),
+        _ => Err("时间格式不对"),
+    }
+}
+
+// 解析 +08:00、-5、UTC+8 格式的时区，返回偏移分钟数
+fn parse_offset(tz: &str) -> Result<i32, &'static str> {
+    let tz = tz.trim_start_matches("UTC").trim_start_matches("GMT");
+    let (sign, rest) = if let Some(rest) = tz.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = tz.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return Err("时区格式不对");
+    };
+    let v: Vec<&str> = rest.split(':').collect();
+    let (hour, minute) = match v.len() {
+        1 => (v[0].parse::<i32>(), Ok(0)),
+        2 => (v[0].parse::<i32>(), v[1].parse::<i32>()),
+        _ => return Err("时区格式不对"),
+    };
+    match (hour, minute) {
+        (Ok(hour), Ok(minute)) if hour <= 14 && (0..60).contains(&minute) => {
+            Ok(sign * (hour * 60 + minute))
+        }
+        _ => Err("时区格式不对"),
+    }
+}
+
+// 把偏移分钟数格式化为 +08:00
+fn format_offset(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("{}{:02}:{:02}", sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+impl QuietHours {
+    // 时区为空时使用服务器时区
+    pub fn parse(start: &str, end: &str, tz: Option<&str>) -> Result<QuietHours, &'static str> {
+        let start = parse_minutes(start)?;
+        let end = parse_minutes(end)?;
+        if start == end {
+            return Err("开始和结束时间不能相同");
+        }
+        let offset = match tz {
+            Some(tz) => parse_offset(tz)?,
+            None => chrono::Local::now().offset().local_minus_utc() / 60,
+        };
+        Ok(QuietHours { start, end, offset })
+    }
+
+    // 给定时间是否在免打扰时段内
+    pub fn contains(&self, timestamp: i64) -> bool {
+        let minute = ((timestamp + i64::from(self.offset) * 60).rem_euclid(86400) / 60) as u32;
+        if self.start < self.end {
+            minute >= self.start && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{:02}:{:02}-{:02}:{:02} (UTC{})",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60,
+            format_offset(self.offset)
+        )
+    }
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct User {
     pub id: String,
     pub name: String,
     pub owns: Vec<String>,
     pub subscribes: Vec<String>,
+    // 频道id/订阅设置
+    #[serde(default)]
+    pub options: HashMap<String, SubscribeOption>,
+    #[serde(default)]
+    pub quiet: Option<QuietHours>,
+}
+
+impl User {
+    pub fn get_option(&self, channel: &str) -> SubscribeOption {
+        self.options.get(channel).cloned().unwrap_or_default()
+    }
 }
 
 pub struct UserInterface {
@@ -55,6 +277,8 @@ impl UserInterface {
                     name: new_name,
                     owns: Vec::<String>::new(),
                     subscribes: Vec::<String>::new(),
+                    options: HashMap::new(),
+                    quiet: None,
                 };
                 let json_string = serde_json::to_string(&new_user).unwrap();
                 self.storage.put_single(id, &rkv::Value::Json(&json_string));
@@ -113,6 +337,40 @@ impl UserInterface {
                         break;
                     }
                 }
+                _user.options.remove(channel);
+                let json_string = serde_json::to_string(&_user).unwrap();
+                self.storage
+                    .put_single(user, &rkv::Value::Json(&json_string));
+                Ok(true)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn set_quiet(&self, user: &str, quiet: Option<QuietHours>) -> Result<bool, &str> {
+        match self.get_user(user) {
+            Ok(mut _user) => {
+                _user.quiet = quiet;
+                let json_string = serde_json::to_string(&_user).unwrap();
+                self.storage
+                    .put_single(user, &rkv::Value::Json(&json_string));
+                Ok(true)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // 修改订阅设置，只能修改已经订阅的频道
+    pub fn set_subscribe_option<F>(&self, user: &str, channel: &str, f: F) -> Result<bool, &str>
+    where
+        F: FnOnce(&mut SubscribeOption),
+    {
+        match self.get_user(user) {
+            Ok(mut _user) => {
+                if !_user.subscribes.contains(&channel.to_string()) {
+                    return Err("没有订阅该频道");
+                }
+                f(_user.options.entry(channel.to_string()).or_default());
                 let json_string = serde_json::to_string(&_user).unwrap();
                 self.storage
                     .put_single(user, &rkv::Value::Json(&json_string));
@@ -135,6 +393,40 @@ impl UserInterface {
         }
     }
 
+    // 在调用方的写事务中把频道从一个用户的owns移到另一个用户
+    pub fn transfer_channel(
+        &self,
+        writer: &mut rkv::Writer,
+        from: &str,
+        to: &str,
+        channel: &str,
+    ) -> Result<bool, &str> {
+        let mut from_user: User = match self.storage.get_json(writer, from) {
+            Some(user_string) => serde_json::from_str(&user_string).unwrap(),
+            None => return Err("未找到用户"),
+        };
+        let mut to_user: User = match self.storage.get_json(writer, to) {
+            Some(user_string) => serde_json::from_str(&user_string).unwrap(),
+            None => return Err("未找到用户"),
+        };
+        if super::channel::exceeds_quota(
+            to,
+            to_user.owns.len(),
+            super::CONFIG.max_channels_per_owner,
+        ) {
+            return Err("创建的频道数量已达上限");
+        }
+        from_user.owns.retain(|chn| chn != channel);
+        if !to_user.owns.iter().any(|chn| chn == channel) {
+            to_user.owns.push(channel.to_string());
+        }
+        self.storage
+            .put_json(writer, from, &serde_json::to_string(&from_user).unwrap());
+        self.storage
+            .put_json(writer, to, &serde_json::to_string(&to_user).unwrap());
+        Ok(true)
+    }
+
     pub fn user_del_channel(&self, user: &str, channel: &str) -> Result<bool, &str> {
         match self.get_user(user) {
             Ok(mut _user) => {
@@ -144,7 +436,6 @@ impl UserInterface {
                         break;
                     }
                 }
-                _user.owns.push(channel.to_string());
                 let json_string = serde_json::to_string(&_user).unwrap();
                 self.storage
                     .put_single(user, &rkv::Value::Json(&json_string));
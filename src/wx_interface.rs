@@ -9,6 +9,29 @@ struct GetTokenResult {
     expires_in: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct OAuthResult {
+    #[serde(default)]
+    openid: String,
+    #[serde(default)]
+    errcode: i32,
+    #[serde(default)]
+    errmsg: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct QrcodeResult {
+    #[serde(default)]
+    ticket: String,
+    #[serde(default)]
+    errcode: i32,
+    #[serde(default)]
+    errmsg: String,
+}
+
+// 临时二维码的最长有效期，单位秒
+pub const QRCODE_MAX_EXPIRE: i64 = 30 * 24 * 3600;
+
 #[derive(Debug, Serialize, Deserialize)]
 struct TemplateResult {
     errcode: i32,
@@ -132,6 +155,103 @@ impl WxInterface {
         debug!("{:?}", tmpres);
         debug!("{:?}", result.text().unwrap());
     }
+
+    // 客服消息，只能发给48小时内和公众号互动过的用户
+    pub fn send_text(&self, user: &str, text: &str) {
+        let post = json!({
+            "touser": user,
+            "msgtype": "text",
+            "text": {
+                "content": text
+            }
+        });
+        debug!("custom req:{}", &post.to_string());
+        let res: Result<TemplateResult, _> = reqwest::Client::new()
+            .post("https://api.weixin.qq.com/cgi-bin/message/custom/send")
+            .query(&[("access_token", &self.get_access_token().access_token)])
+            .json(&post)
+            .send()
+            .and_then(|mut result| result.json());
+        debug!("custom res:{:?}", res);
+    }
+
+    // 生成带参数的临时二维码，返回二维码图片地址
+    pub fn create_qrcode(&self, scene: &str, expire_seconds: i64) -> Result<String, &str> {
+        let post = json!({
+            "expire_seconds": expire_seconds.min(QRCODE_MAX_EXPIRE),
+            "action_name": "QR_STR_SCENE",
+            "action_info": {
+                "scene": {
+                    "scene_str": scene
+                }
+            }
+        });
+        debug!("qrcode req:{}", &post.to_string());
+        let res: QrcodeResult = match reqwest::Client::new()
+            .post("https://api.weixin.qq.com/cgi-bin/qrcode/create")
+            .query(&[("access_token", &self.get_access_token().access_token)])
+            .json(&post)
+            .send()
+            .and_then(|mut result| result.json())
+        {
+            Ok(res) => res,
+            Err(err) => {
+                debug!("qrcode err:{}", err);
+                return Err("生成二维码失败");
+            }
+        };
+        debug!("qrcode res:{:?}", res);
+        if res.errcode != 0 || res.ticket.is_empty() {
+            return Err("生成二维码失败");
+        }
+        let ticket: String = url::form_urlencoded::byte_serialize(res.ticket.as_bytes()).collect();
+        Ok(format!(
+            "https://mp.weixin.qq.com/cgi-bin/showqrcode?ticket={}",
+            ticket
+        ))
+    }
+
+    // 网页授权跳转地址，只需要openid所以使用snsapi_base
+    pub fn get_oauth_url(&self, redirect: &str) -> String {
+        let redirect: String = url::form_urlencoded::byte_serialize(redirect.as_bytes()).collect();
+        format!(
+            "https://open.weixin.qq.com/connect/oauth2/authorize?appid={}&redirect_uri={}&response_type=code&scope=snsapi_base&state=server_tan#wechat_redirect",
+            super::CONFIG.appid, redirect
+        )
+    }
+
+    // 通过网页授权的code换取用户openid
+    pub fn get_oauth_openid(&self, code: &str) -> Result<String, &str> {
+        let config = super::CONFIG.clone();
+        let res: OAuthResult = match reqwest::Client::new()
+            .get("https://api.weixin.qq.com/sns/oauth2/access_token")
+            .query(&[("appid", &config.appid)])
+            .query(&[("secret", &config.secret)])
+            .query(&[("code", code)])
+            .query(&[("grant_type", "authorization_code")])
+            .send()
+            .and_then(|mut result| result.json())
+        {
+            Ok(res) => res,
+            Err(err) => {
+                debug!("oauth err:{}", err);
+                return Err("网页授权失败");
+            }
+        };
+        debug!("oauth res:{:?}", res);
+        if res.errcode != 0 || res.openid.is_empty() {
+            Err("网页授权失败")
+        } else {
+            Ok(res.openid)
+        }
+    }
+}
+
+// 在后台线程发送客服消息，不阻塞对用户的回复
+pub fn send_text_background(user: &str, text: &str) {
+    let user = user.to_string();
+    let text = text.to_string();
+    std::thread::spawn(move || INTERFACE.send_text(&user, &text));
 }
 
 pub fn check_signature(signature: &str, timestamp: &str, nonce: &str) -> bool {
@@ -1,6 +1,6 @@
 use chrono;
 
-use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::{Reader, Writer};
 use std::io::Cursor;
 
@@ -111,3 +111,86 @@ pub fn gen_message_reply(to: &str, from: &str, content: &str) -> String {
     let result = writer.into_inner().into_inner();
     String::from_utf8(result).unwrap()
 }
+
+pub struct FeedEntry {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub updated: String,
+    pub content: String,
+}
+
+fn write_text_element<W: std::io::Write>(writer: &mut Writer<W>, name: &[u8], text: &str) {
+    writer
+        .write_event(Event::Start(BytesStart::borrowed_name(name)))
+        .unwrap();
+    writer
+        .write_event(Event::Text(BytesText::from_plain_str(text)))
+        .unwrap();
+    writer
+        .write_event(Event::End(BytesEnd::borrowed(name)))
+        .unwrap();
+}
+
+fn write_link<W: std::io::Write>(writer: &mut Writer<W>, href: &str) {
+    let mut tag_link = BytesStart::borrowed_name(b"link");
+    tag_link.push_attribute(("href", href));
+    writer.write_event(Event::Empty(tag_link)).unwrap();
+}
+
+// 生成Atom订阅源
+pub fn gen_atom_feed(
+    id: &str,
+    title: &str,
+    link: &str,
+    updated: &str,
+    entries: &[FeedEntry],
+) -> String {
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    writer
+        .write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"utf-8"), None)))
+        .unwrap();
+
+    let mut tag_feed = BytesStart::borrowed_name(b"feed");
+    tag_feed.push_attribute(("xmlns", "http://www.w3.org/2005/Atom"));
+    writer.write_event(Event::Start(tag_feed)).unwrap();
+    write_text_element(&mut writer, b"id", id);
+    write_text_element(&mut writer, b"title", title);
+    write_text_element(&mut writer, b"updated", updated);
+    write_link(&mut writer, link);
+    writer
+        .write_event(Event::Start(BytesStart::borrowed_name(b"author")))
+        .unwrap();
+    write_text_element(&mut writer, b"name", title);
+    writer
+        .write_event(Event::End(BytesEnd::borrowed(b"author")))
+        .unwrap();
+
+    for entry in entries {
+        writer
+            .write_event(Event::Start(BytesStart::borrowed_name(b"entry")))
+            .unwrap();
+        write_text_element(&mut writer, b"id", &entry.id);
+        write_text_element(&mut writer, b"title", &entry.title);
+        write_text_element(&mut writer, b"updated", &entry.updated);
+        write_link(&mut writer, &entry.link);
+        let mut tag_content = BytesStart::borrowed_name(b"content");
+        tag_content.push_attribute(("type", "html"));
+        writer.write_event(Event::Start(tag_content)).unwrap();
+        writer
+            .write_event(Event::Text(BytesText::from_plain_str(&entry.content)))
+            .unwrap();
+        writer
+            .write_event(Event::End(BytesEnd::borrowed(b"content")))
+            .unwrap();
+        writer
+            .write_event(Event::End(BytesEnd::borrowed(b"entry")))
+            .unwrap();
+    }
+
+    writer
+        .write_event(Event::End(BytesEnd::borrowed(b"feed")))
+        .unwrap();
+    let result = writer.into_inner().into_inner();
+    String::from_utf8(result).unwrap()
+}